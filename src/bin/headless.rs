@@ -0,0 +1,382 @@
+use std::{collections::VecDeque, env, fs::{self, File}, io::BufWriter, ops::RangeInclusive, path::{Path, PathBuf}, process};
+
+use gameboy_emolator::{
+    gameboy::{cpu::{registers::Reg8, Cpu}, screen::image::{self, save_png}, trace::{TraceOptions, Tracer}},
+    rom, Config, Event, GameBoy, Key, Model, Renderer, RtcClock, CYCLES_PER_FRAME,
+};
+
+const USAGE: &str = "\
+usage: headless --rom_path <rom> [options]
+
+  -r, --rom_path <path>   ROM to run
+  -b, --boot-rom <path>   run the given 256 byte DMG boot ROM first
+  -m, --model <name>      dmg0, dmg (default), mgb, sgb, sgb2 or cgb
+      --renderer <name>   scanline (default) or fifo
+      --unrestricted-access
+                          let the CPU access VRAM and OAM while the PPU uses them
+      --rtc <clock>       MBC3 clock source: host (default) or emulated cycles
+  -f, --frames <n>        number of frames to run (default 60)
+  -c, --cycles <n>        run for a T-cycle budget instead of a frame count
+  -i, --input <path>      scripted input, one '<frame> <key> <down|up>' per line
+  -o, --out <dir>         output directory for PNG files (default .)
+  -e, --every <n>         additionally dump every n-th frame
+      --compare <png>     compare the last frame against a reference screenshot,
+                          writes diff.png and fails if they differ
+  -d, --disasm <[bank:]addr>
+                          disassemble 32 instructions (hex address) after the run
+  -t, --trace <path>      write a Gameboy Doctor trace (LY reads 0x90 while tracing)
+      --trace-pc <start-end>
+                          only trace instructions in this hex address range
+      --trace-bank <n>    only trace instructions executed from this ROM bank (hex)
+      --trace-last <n>    only keep the last n instructions, written at exit or on a panic
+      --trace-keep-ly     let LY follow the PPU while tracing";
+
+/// Instructions listed by `--disasm`
+const DISASM_COUNT: usize = 32;
+
+enum Budget {
+    Frames(u64),
+    Cycles(u64),
+}
+
+struct Args {
+    rom_path: String,
+    boot_rom_path: Option<String>,
+    model: Model,
+    renderer: Renderer,
+    unrestricted_access: bool,
+    rtc_clock: RtcClock,
+    budget: Budget,
+    input_path: Option<String>,
+    out_dir: PathBuf,
+    every: Option<u64>,
+    compare_path: Option<PathBuf>,
+    disasm: Option<(Option<usize>, u16)>,
+    trace_path: Option<String>,
+    trace_options: TraceOptions,
+}
+
+impl Args {
+    fn parse() -> Self {
+        let mut rom_path: Option<String> = None;
+        let mut boot_rom_path = None;
+        let mut model = Model::default();
+        let mut renderer = Renderer::default();
+        let mut unrestricted_access = false;
+        let mut rtc_clock = RtcClock::default();
+        let mut budget = Budget::Frames(60);
+        let mut input_path = None;
+        let mut out_dir = PathBuf::from(".");
+        let mut every = None;
+        let mut compare_path = None;
+        let mut disasm = None;
+        let mut trace_path = None;
+        let mut trace_options = TraceOptions::default();
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match &arg[..] {
+                "-r" | "--rom_path" => rom_path = args.next(),
+                "-b" | "--boot-rom" => boot_rom_path = args.next(),
+                "-m" | "--model" => {
+                    model = match args.next().as_deref().and_then(Model::from_name) {
+                        Some(value) => value,
+                        None => panic!("argument '{}' expects a model\n{}", arg, USAGE)
+                    };
+                },
+                "--renderer" => {
+                    renderer = match args.next().as_deref().and_then(Renderer::from_name) {
+                        Some(value) => value,
+                        None => panic!("argument '{}' expects a renderer\n{}", arg, USAGE)
+                    };
+                },
+                "--unrestricted-access" => unrestricted_access = true,
+                "--rtc" => {
+                    rtc_clock = match args.next().as_deref().and_then(RtcClock::from_name) {
+                        Some(value) => value,
+                        None => panic!("argument '{}' expects a clock source\n{}", arg, USAGE)
+                    };
+                },
+                "-f" | "--frames" => budget = Budget::Frames(Self::number(&arg, args.next())),
+                "-c" | "--cycles" => budget = Budget::Cycles(Self::number(&arg, args.next())),
+                "-i" | "--input" => input_path = args.next(),
+                "-o" | "--out" => {
+                    if let Some(dir) = args.next() {
+                        out_dir = PathBuf::from(dir);
+                    }
+                },
+                "-e" | "--every" => every = Some(Self::number(&arg, args.next())).filter(|&n| n > 0),
+                "--compare" => compare_path = args.next().map(PathBuf::from),
+                "-d" | "--disasm" => {
+                    disasm = match args.next().as_deref().and_then(Self::location) {
+                        Some(value) => Some(value),
+                        None => panic!("argument '{}' expects [bank:]addr in hex\n{}", arg, USAGE)
+                    };
+                },
+                "-t" | "--trace" => trace_path = args.next(),
+                "--trace-pc" => {
+                    trace_options.pc_range = match args.next().as_deref().and_then(Self::range) {
+                        Some(value) => Some(value),
+                        None => panic!("argument '{}' expects start-end in hex\n{}", arg, USAGE)
+                    };
+                },
+                "--trace-bank" => {
+                    trace_options.rom_bank = match args.next().map(|bank| usize::from_str_radix(&bank, 16)) {
+                        Some(Ok(value)) => Some(value),
+                        _ => panic!("argument '{}' expects a bank number in hex\n{}", arg, USAGE)
+                    };
+                },
+                "--trace-last" => trace_options.last = Some(Self::number(&arg, args.next()) as usize),
+                "--trace-keep-ly" => trace_options.keep_ly = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                },
+                _ => println!("Unknown argument {}. Skipping...", arg)
+            }
+        }
+
+        let rom_path = match rom_path {
+            Some(value) => value,
+            None => panic!("required argument '--rom_path <rom_path>' not found.\n{}", USAGE)
+        };
+
+        Self {
+            rom_path, boot_rom_path, model, renderer, unrestricted_access, rtc_clock, budget, input_path, out_dir, every, compare_path,
+            disasm, trace_path, trace_options,
+        }
+    }
+
+    /// Parses `start-end`, both in hex
+    fn range(value: &str) -> Option<RangeInclusive<u16>> {
+        let (start, end) = value.split_once('-')?;
+
+        Some(u16::from_str_radix(start, 16).ok()?..=u16::from_str_radix(end, 16).ok()?)
+    }
+
+    /// Parses `bank:addr` or a plain `addr`, both in hex
+    fn location(value: &str) -> Option<(Option<usize>, u16)> {
+        match value.split_once(':') {
+            Some((bank, addr)) => Some((
+                Some(usize::from_str_radix(bank, 16).ok()?),
+                u16::from_str_radix(addr, 16).ok()?,
+            )),
+            None => Some((None, u16::from_str_radix(value, 16).ok()?)),
+        }
+    }
+
+    fn number(arg: &str, value: Option<String>) -> u64 {
+        match value.as_deref().map(str::parse) {
+            Some(Ok(value)) => value,
+            _ => panic!("argument '{}' expects a number", arg)
+        }
+    }
+}
+
+/// A single scripted key event, applied before the given frame is emulated
+struct InputEvent {
+    frame: u64,
+    key: Key,
+    pressed: bool,
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    match &name.to_ascii_lowercase()[..] {
+        "right"  => Some(Key::Right),
+        "left"   => Some(Key::Left),
+        "up"     => Some(Key::Up),
+        "down"   => Some(Key::Down),
+        "a"      => Some(Key::A),
+        "b"      => Some(Key::B),
+        "select" => Some(Key::Select),
+        "start"  => Some(Key::Start),
+        _ => None,
+    }
+}
+
+fn load_input_script(path: &str) -> VecDeque<InputEvent> {
+    let script = match fs::read_to_string(path) {
+        Ok(value) => value,
+        Err(err) => panic!("Couldn't load input script: {}", err)
+    };
+
+    let mut events = Vec::new();
+    for (line_number, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() { continue; }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let event = match parts[..] {
+            [frame, key, state] => {
+                let frame = frame.parse().ok();
+                let key = parse_key(key);
+                let pressed = match state {
+                    "down" | "press" => Some(true),
+                    "up" | "release" => Some(false),
+                    _ => None,
+                };
+
+                match (frame, key, pressed) {
+                    (Some(frame), Some(key), Some(pressed)) => Some(InputEvent { frame, key, pressed }),
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+
+        match event {
+            Some(event) => events.push(event),
+            None => panic!("{}:{}: expected '<frame> <key> <down|up>', got '{}'", path, line_number + 1, line)
+        }
+    }
+
+    events.sort_by_key(|event| event.frame);
+    events.into()
+}
+
+fn register_summary(cpu: &Cpu, frames: u64, cycles: u64) -> String {
+    let registers = &cpu.registers;
+
+    format!(
+        "frames: {}\ncycles: {}\n\
+         A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X}\n\
+         SP:{:04X} PC:{:04X} IME:{} HALT:{}\n",
+        frames, cycles,
+        registers.read8(&Reg8::A), registers.flag_register.flags,
+        registers.read8(&Reg8::B), registers.read8(&Reg8::C),
+        registers.read8(&Reg8::D), registers.read8(&Reg8::E),
+        registers.read8(&Reg8::H), registers.read8(&Reg8::L),
+        cpu.stack_pointer, cpu.program_counter,
+        cpu.ime as u8, cpu.halted as u8,
+    )
+}
+
+fn write_png(gb: &GameBoy, path: &Path) {
+    if let Err(err) = save_png(gb.framebuffer(), path) {
+        panic!("Couldn't write {}: {}", path.display(), err);
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let rom = match rom::load_rom(&args.rom_path) {
+        Ok(value) => value,
+        Err(err) => panic!("Couldn't load rom: {}", err)
+    };
+
+    let mut inputs = match &args.input_path {
+        Some(path) => load_input_script(path),
+        None => VecDeque::new(),
+    };
+
+    if let Err(err) = fs::create_dir_all(&args.out_dir) {
+        panic!("Couldn't create output directory: {}", err);
+    }
+
+    let boot_rom = args.boot_rom_path.as_deref().map(|path| match rom::load_boot_rom(path) {
+        Ok(value) => value,
+        Err(err) => panic!("Couldn't load boot rom: {}", err)
+    });
+
+    let mut gb = match GameBoy::with_config(rom, Config {
+        boot_rom,
+        model: args.model,
+        renderer: args.renderer,
+        unrestricted_access: args.unrestricted_access,
+        rtc_clock: args.rtc_clock,
+    }) {
+        Ok(value) => value,
+        Err(err) => panic!("Couldn't load rom: {}", err)
+    };
+    if let Some(path) = &args.trace_path {
+        let file = match File::create(path) {
+            Ok(value) => value,
+            Err(err) => panic!("Couldn't create trace file: {}", err)
+        };
+
+        gb.set_tracer(Some(Tracer::new(Box::new(BufWriter::new(file)), args.trace_options.clone())));
+    }
+
+    let mut frames = 0u64;
+    let mut cycles = 0u64;
+
+    loop {
+        let remaining = match args.budget {
+            Budget::Frames(limit) if frames >= limit => break,
+            Budget::Cycles(limit) if cycles >= limit => break,
+            Budget::Frames(_) => CYCLES_PER_FRAME as u64,
+            Budget::Cycles(limit) => limit - cycles,
+        };
+
+        while inputs.front().is_some_and(|event| event.frame <= frames) {
+            if let Some(event) = inputs.pop_front() {
+                if event.pressed {
+                    gb.key_down(event.key);
+                } else {
+                    gb.key_up(event.key);
+                }
+            }
+        }
+
+        let frame_done = if remaining >= CYCLES_PER_FRAME as u64 {
+            cycles += gb.run_frame() as u64;
+            true
+        } else {
+            // Overshoots the budget by at most one instruction
+            cycles += remaining;
+            gb.run_cycles(remaining as u32)
+        };
+
+        // A partial frame at the end of a cycle budget doesn't count, the
+        // input script numbers whole frames
+        if frame_done {
+            frames += 1;
+        }
+
+        // Nobody listens, so don't let the sample buffers grow forever
+        gb.get_audio_samples();
+        gb.take_serial_output();
+
+        for event in gb.take_events() {
+            match event {
+                Event::Rumble(on) => println!("frame {}: rumble {}", frames, if on { "on" } else { "off" }),
+            }
+        }
+
+        if frame_done && args.every.is_some_and(|every| frames.is_multiple_of(every)) {
+            write_png(&gb, &args.out_dir.join(format!("frame_{:06}.png", frames)));
+        }
+    }
+
+    write_png(&gb, &args.out_dir.join("final.png"));
+
+    let summary = register_summary(gb.cpu(), frames, cycles);
+    print!("{}", summary);
+
+    if let Some((bank, addr)) = args.disasm {
+        for instruction in gb.disassemble(bank, addr, DISASM_COUNT) {
+            println!("{}", instruction);
+        }
+    }
+    if let Err(err) = fs::write(args.out_dir.join("summary.txt"), &summary) {
+        panic!("Couldn't write summary: {}", err);
+    }
+
+    if let Some(path) = &args.compare_path {
+        let reference = match image::load_png(path) {
+            Ok(value) => value,
+            Err(err) => panic!("Couldn't load reference {}: {}", path.display(), err)
+        };
+
+        if let Some(diff) = image::compare(gb.framebuffer(), &reference) {
+            let diff_path = args.out_dir.join("diff.png");
+            if let Err(err) = save_png(&diff.image, &diff_path) {
+                panic!("Couldn't write {}: {}", diff_path.display(), err);
+            }
+
+            println!("{} pixels differ from {}, see {}", diff.pixels, path.display(), diff_path.display());
+            process::exit(1);
+        }
+        println!("matches {}", path.display());
+    }
+}
@@ -0,0 +1,116 @@
+use std::{env, path::{Path, PathBuf}, process};
+
+use gameboy_emolator::{rom, testrom::{self, Outcome, TestResult, DEFAULT_TIMEOUT_FRAMES, MOONEYE_TIMEOUT_FRAMES}};
+
+const USAGE: &str = "\
+usage: testrom <suite> <rom or directory> [options]
+
+  suites:
+    blargg                Blargg's ROMs, results via serial port or 0xA000
+    mooneye               Mooneye's ROMs, results in B-L at the LD B,B breakpoint,
+                          ROMs for other models than DMG are skipped
+
+  -t, --timeout <s>       emulated seconds before a ROM counts as hung (default 120, mooneye 20)
+  -v, --verbose           print the whole text output of every ROM";
+
+enum Suite {
+    Blargg,
+    Mooneye,
+}
+
+struct Args {
+    suite: Suite,
+    path: PathBuf,
+    timeout_frames: Option<u64>,
+    verbose: bool,
+}
+
+impl Args {
+    fn parse() -> Self {
+        let mut suite = None;
+        let mut path = None;
+        let mut timeout_frames = None;
+        let mut verbose = false;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match &arg[..] {
+                "-t" | "--timeout" => {
+                    timeout_frames = match args.next().as_deref().map(str::parse::<u64>) {
+                        Some(Ok(seconds)) => Some(seconds * 60),
+                        _ => panic!("argument '{}' expects a number", arg)
+                    };
+                },
+                "-v" | "--verbose" => verbose = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                },
+                "blargg" if suite.is_none() => suite = Some(Suite::Blargg),
+                "mooneye" if suite.is_none() => suite = Some(Suite::Mooneye),
+                _ if suite.is_some() && path.is_none() => path = Some(PathBuf::from(arg)),
+                _ => println!("Unknown argument {}. Skipping...", arg)
+            }
+        }
+
+        let (Some(suite), Some(path)) = (suite, path) else {
+            panic!("expected a suite and a path\n{}", USAGE)
+        };
+
+        Self { suite, path, timeout_frames, verbose }
+    }
+}
+
+fn run(suite: &Suite, path: &Path, timeout_frames: u64) -> TestResult {
+    let rom = match rom::load_rom(&path.to_string_lossy()) {
+        Ok(value) => value,
+        Err(err) => panic!("Couldn't load rom {}: {}", path.display(), err)
+    };
+
+    let result = match suite {
+        Suite::Blargg => testrom::run_blargg(rom, timeout_frames),
+        Suite::Mooneye => testrom::run_mooneye(rom, timeout_frames),
+    };
+
+    match result {
+        Ok(value) => value,
+        Err(err) => panic!("Couldn't load rom {}: {}", path.display(), err)
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let roms = match testrom::find_roms(&args.path) {
+        Ok(value) => value,
+        Err(err) => panic!("Couldn't read {}: {}", args.path.display(), err)
+    };
+
+    let timeout_frames = args.timeout_frames.unwrap_or(match args.suite {
+        Suite::Blargg => DEFAULT_TIMEOUT_FRAMES,
+        Suite::Mooneye => MOONEYE_TIMEOUT_FRAMES,
+    });
+
+    let mut results = Vec::new();
+    for path in roms {
+        if matches!(args.suite, Suite::Mooneye) && !testrom::mooneye_runs_on_dmg(&path) {
+            continue;
+        }
+
+        let result = run(&args.suite, &path, timeout_frames);
+        let name = path.strip_prefix(&args.path).unwrap_or(&path).display().to_string();
+        let name = if name.is_empty() { path.display().to_string() } else { name };
+
+        if args.verbose {
+            println!("== {} ({})\n{}\n", name, result.outcome, result.output);
+        }
+
+        results.push((name, result));
+    }
+
+    print!("{}", testrom::results_table(&results));
+
+    if results.iter().any(|(_, result)| result.outcome != Outcome::Passed) {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod args;
+pub mod audio;
+pub mod window;
\ No newline at end of file
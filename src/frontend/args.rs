@@ -0,0 +1,76 @@
+use std::{env, path::PathBuf};
+
+use gameboy_emolator::{Model, Renderer, RtcClock};
+
+pub struct Args {
+    pub rom_path: String,
+    pub save_dir: Option<PathBuf>,
+    pub boot_rom_path: Option<String>,
+    pub model: Model,
+    pub renderer: Renderer,
+    /// Let the CPU access VRAM and OAM in every PPU mode
+    pub unrestricted_access: bool,
+    pub rtc_clock: RtcClock,
+    /// Print the cartridge header and exit
+    pub info: bool,
+}
+
+impl Args {
+    pub fn parse() -> Self {
+        let mut rom_path: Option<String> = None;
+        let mut save_dir: Option<PathBuf> = None;
+        let mut boot_rom_path: Option<String> = None;
+        let mut model = Model::default();
+        let mut renderer = Renderer::default();
+        let mut unrestricted_access = false;
+        let mut rtc_clock = RtcClock::default();
+        let mut info = false;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match &arg[..] {
+                "-r" | "--rom_path" => {
+                    if let Some(arg_rom_path) = args.next() {
+                        rom_path = Some(arg_rom_path)
+                    }
+                },
+                "-s" | "--save-dir" => save_dir = args.next().map(PathBuf::from),
+                "-b" | "--boot-rom" => boot_rom_path = args.next(),
+                "-m" | "--model" => model = Self::model(args.next()),
+                "--renderer" => renderer = Self::renderer(args.next()),
+                "--unrestricted-access" => unrestricted_access = true,
+                "--rtc" => rtc_clock = Self::rtc_clock(args.next()),
+                "--info" => info = true,
+                _ => println!("Unknown argument {}. Skipping...", arg)
+            }
+        }
+
+        let rom_path = match rom_path {
+            Some(value) => value,
+            None => panic!("required argument '--rom_path <rom_path>' not found.")
+        };
+
+        Self { rom_path, save_dir, boot_rom_path, model, renderer, unrestricted_access, rtc_clock, info }
+    }
+
+    fn model(name: Option<String>) -> Model {
+        match name.as_deref().and_then(Model::from_name) {
+            Some(model) => model,
+            None => panic!("argument '--model' expects one of dmg0, dmg, mgb, sgb, sgb2, cgb")
+        }
+    }
+
+    fn renderer(name: Option<String>) -> Renderer {
+        match name.as_deref().and_then(Renderer::from_name) {
+            Some(renderer) => renderer,
+            None => panic!("argument '--renderer' expects one of scanline, fifo")
+        }
+    }
+
+    fn rtc_clock(name: Option<String>) -> RtcClock {
+        match name.as_deref().and_then(RtcClock::from_name) {
+            Some(clock) => clock,
+            None => panic!("argument '--rtc' expects one of host, emulated")
+        }
+    }
+}
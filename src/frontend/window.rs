@@ -1,7 +1,6 @@
 use minifb::{Key, Window, WindowOptions};
 
-use super::framebuffer::{Framebuffer, SCREEN_H, SCREEN_W};
-use crate::gameboy::joypad::Key as JoypadKey;
+use gameboy_emolator::{Framebuffer, Key as JoypadKey, SCREEN_H, SCREEN_W};
 
 pub struct ScreenWindow {
     window: Window,
@@ -10,13 +9,15 @@ pub struct ScreenWindow {
 
 impl ScreenWindow {
     pub fn new(title: &str, scale: usize) -> Self {
-        let mut options = WindowOptions::default();
-        options.resize = false;
-        options.scale = match scale {
-            1 => minifb::Scale::X1,
-            2 => minifb::Scale::X2,
-            4 => minifb::Scale::X4,
-            _ => minifb::Scale::X1,
+        let options = WindowOptions {
+            resize: false,
+            scale: match scale {
+                1 => minifb::Scale::X1,
+                2 => minifb::Scale::X2,
+                4 => minifb::Scale::X4,
+                _ => minifb::Scale::X1,
+            },
+            ..WindowOptions::default()
         };
 
         let window = Window::new(title, SCREEN_W, SCREEN_H, options)
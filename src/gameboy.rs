@@ -1,54 +1,189 @@
-pub mod audio;
 pub mod apu;
+pub mod bus;
+pub mod cartridge;
 pub mod cpu;
+pub mod disasm;
 pub mod joypad;
 pub mod mmu;
+pub mod model;
 pub mod ppu;
+pub mod scheduler;
 pub mod screen;
 pub mod timer;
+pub mod trace;
 
 use apu::Apu;
+use cartridge::{rtc::RtcClock, Cartridge};
 use cpu::Cpu;
+use disasm::Instruction;
 use joypad::Key;
 use mmu::Mmu;
-use ppu::Ppu;
+use model::Model;
+use ppu::{Ppu, Renderer};
+use screen::framebuffer::Framebuffer;
 use timer::Timer;
+use trace::Tracer;
 
+use crate::rom::{CartridgeHeader, RomError, BOOT_ROM_SIZE};
 
+/// T-cycles the PPU needs for one full frame (154 lines * 456 dots)
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+/// Things happening inside the console a frontend may want to react to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// The rumble motor of a rumble cartridge was switched on (true) or off
+    Rumble(bool),
+}
+
+/// How the console is put together
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// 256 byte DMG boot ROM. Without one the console starts directly at
+    /// 0x0100 with the state the boot ROM would have left behind.
+    pub boot_rom: Option<Vec<u8>>,
+    /// Console revision, decides the state the (skipped) boot ROM leaves behind
+    pub model: Model,
+    /// Scanline renderer (fast) or pixel FIFO (mid-line effects)
+    pub renderer: Renderer,
+    /// Lets the CPU access VRAM during mode 3 and OAM during modes 2 and 3,
+    /// which the real hardware blocks. For debugging only.
+    pub unrestricted_access: bool,
+    /// What the MBC3 real-time clock counts, the host clock by default
+    pub rtc_clock: RtcClock,
+}
+
+/// The whole console: the CPU and the memory bus with everything attached to it
 pub struct GameBoy {
     cpu: Cpu,
     mmu: Mmu,
-    pub ppu: Ppu,
+
+    rumble: bool,
+    events: Vec<Event>,
+    tracer: Option<Tracer>,
 }
 
 impl GameBoy {
-    pub fn new(rom: Vec<u8>) -> Self {
-        Self {
-            cpu: Cpu::new(), 
-            mmu: Mmu::new(
-                rom, 
-                Apu::new(), 
-                Timer::new()
-            ),
-            ppu: Ppu::new(),
-        }
+    /// Creates a DMG with the given cartridge ROM inserted,
+    /// starting in the post-boot state at 0x0100
+    ///
+    /// * `result` - Fails if the header is broken or the cartridge type is not supported
+    pub fn new(rom: Vec<u8>) -> Result<Self, RomError> {
+        Self::with_config(rom, Config::default())
     }
 
+    /// Creates a console with the given cartridge ROM inserted. With a boot
+    /// ROM in `config` it starts from power on at 0x0000.
+    ///
+    /// * `result` - Fails if the header is broken, the cartridge type is not
+    ///   supported or the boot ROM has the wrong size
+    pub fn with_config(rom: Vec<u8>, config: Config) -> Result<Self, RomError> {
+        let mut cartridge = Cartridge::new(rom)?;
+        cartridge.set_rtc_clock(config.rtc_clock);
+
+        let mut mmu = Mmu::new(
+            cartridge,
+            Apu::new(),
+            Timer::new(),
+            Ppu::new()
+        );
+        mmu.set_renderer(config.renderer);
+        mmu.set_restrict_access(!config.unrestricted_access);
+
+        let cpu = match config.boot_rom {
+            Some(boot_rom) if boot_rom.len() != BOOT_ROM_SIZE => return Err(RomError::InvalidBootRom(boot_rom.len())),
+            Some(boot_rom) => {
+                mmu.map_boot_rom(boot_rom);
+                Cpu::power_on()
+            },
+            None => {
+                mmu.post_boot(config.model);
+                Cpu::post_boot(config.model, mmu.cartridge_header())
+            },
+        };
+
+        Ok(Self {
+            cpu,
+            mmu,
+
+            rumble: false,
+            events: Vec::new(),
+            tracer: None,
+        })
+    }
+
+    /// Executes a single CPU instruction and advances the rest of the system
+    /// by the same amount of cycles
+    ///
+    /// * `result` - Returns true if a new frame has been completed
     pub fn step(&mut self) -> bool {
+        self.step_cycles().1
+    }
+
+    /// Runs until the PPU has completed the next frame. If the LCD is switched
+    /// off no frame will ever be completed, so at most one frame worth of
+    /// cycles is executed.
+    ///
+    /// * `result` - Returns the number of cycles that were executed
+    pub fn run_frame(&mut self) -> u32 {
+        let mut elapsed = 0;
+
+        while elapsed < CYCLES_PER_FRAME {
+            let (cycles, frame_done) = self.step_cycles();
+            elapsed += cycles as u32;
+
+            if frame_done {
+                break;
+            }
+        }
+
+        elapsed
+    }
+
+    /// Runs for at least `cycles` T-cycles. Instructions are never split,
+    /// so the budget can be overshot by a few cycles.
+    ///
+    /// * `result` - Returns true if at least one frame has been completed
+    pub fn run_cycles(&mut self, cycles: u32) -> bool {
+        let mut elapsed = 0;
+        let mut frame_done = false;
+
+        while elapsed < cycles {
+            let (step_cycles, step_frame_done) = self.step_cycles();
+            elapsed += step_cycles as u32;
+            frame_done |= step_frame_done;
+        }
+
+        frame_done
+    }
+
+    fn step_cycles(&mut self) -> (u8, bool) {
+        if let Some(tracer) = &mut self.tracer
+            && let Err(err) = tracer.trace(&self.cpu, &self.mmu) {
+            eprintln!("Couldn't write trace, stopping it: {}", err);
+            self.set_tracer(None);
+        }
+
+        // The CPU advances the rest of the system itself, M-cycle by M-cycle
         let cycles = self.cpu.step(&mut self.mmu);
-        
-        self.mmu.tick(cycles);
-        self.mmu.tick_apu(cycles);
-        self.ppu.step(cycles as u16, &mut self.mmu);
-
-        if self.ppu.frame_ready {
-            self.ppu.frame_ready = false;
-            return true;
+
+        let rumble = self.mmu.rumble();
+        if rumble != self.rumble {
+            self.rumble = rumble;
+            self.events.push(Event::Rumble(rumble));
         }
 
-        false
+        (cycles, self.mmu.take_frame_ready())
+    }
+
+    /// The last completed (or currently drawn) picture
+    pub fn framebuffer(&self) -> &Framebuffer {
+        self.mmu.framebuffer()
     }
 
+    /// Drains all audio samples produced since the last call
+    ///
+    /// * `result` - Returns the left and right channel at 44.1 kHz
     pub fn get_audio_samples(&mut self) -> (Vec<f32>, Vec<f32>) {
         self.mmu.get_audio_samples()
     }
@@ -60,4 +195,71 @@ impl GameBoy {
     pub fn key_up(&mut self, key: Key) {
         self.mmu.key_up(key);
     }
-}
\ No newline at end of file
+
+    /// Drains the bytes the game sent over the link cable since the last call
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.mmu.take_serial_output()
+    }
+
+    /// Returns whether a `LD B, B` breakpoint was executed since the last call
+    pub fn take_breakpoint(&mut self) -> bool {
+        std::mem::take(&mut self.cpu.breakpoint)
+    }
+
+    /// Drains all events that happened since the last call
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Reads a byte from the CPU address space. VRAM and OAM stay readable
+    /// while the PPU holds them.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.mmu.peek8(addr)
+    }
+
+    /// Disassembles `count` instructions starting at `addr`. With a `bank`
+    /// the ROM area is read from that bank instead of the mapped one.
+    pub fn disassemble(&self, bank: Option<usize>, addr: u16, count: usize) -> Vec<Instruction> {
+        disasm::disassemble_mmu(&self.mmu, bank, addr, count)
+    }
+
+    /// Writes a byte into the CPU address space, exactly as the CPU would
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.mmu.write8(addr, value);
+    }
+
+    /// Battery backed cartridge state in `.sav` layout (RAM followed by the
+    /// RTC trailer, if any), `None` if the cartridge has no battery
+    pub fn save_data(&mut self) -> Option<Vec<u8>> {
+        self.mmu.save_data()
+    }
+
+    /// Restores battery backed cartridge state from a previous session
+    ///
+    /// * `result` - Returns false if the size did not match the cartridge
+    pub fn load_save_data(&mut self, data: &[u8]) -> bool {
+        self.mmu.load_save_data(data)
+    }
+
+    /// Returns whether the cartridge RAM or clock was written since the last call
+    pub fn take_battery_ram_dirty(&mut self) -> bool {
+        self.mmu.take_eram_dirty() && self.mmu.has_battery()
+    }
+
+    /// The parsed header of the inserted cartridge
+    pub fn header(&self) -> &CartridgeHeader {
+        self.mmu.cartridge_header()
+    }
+
+    /// Starts logging every executed instruction, or stops it with `None`.
+    /// LY reads 0x90 while tracing, as Gameboy Doctor requires, unless
+    /// `TraceOptions::keep_ly` is set.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.mmu.set_fixed_ly(tracer.as_ref().is_some_and(|tracer| !tracer.options().keep_ly));
+        self.tracer = tracer;
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+}
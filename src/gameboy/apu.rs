@@ -22,10 +22,7 @@ pub const DUTY_TABLE: [[u8; 8]; 4] = [
 pub struct Apu {
     pub enabled: bool,
 
-    cycles: u32,
-
     frame_step: u8,
-    frame_counter: u32,
 
     pub ch1: SquareChannel1,
     pub ch2: SquareChannel2,
@@ -52,15 +49,18 @@ pub struct Apu {
     pub sample_buffer_r: Vec<f32>,
 }
 
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Apu {
     pub fn new() -> Self {
         Self {
             enabled: true,
 
-            cycles: 0,
-
             frame_step: 0,
-            frame_counter: 0,
 
             ch1: SquareChannel1::default(),
             ch2: SquareChannel2::default(),
@@ -85,10 +85,42 @@ impl Apu {
         }
     }
 
+    /// Runs the channels for `cycles` T-cycles, in chunks that end at the
+    /// points where a sample is taken
     pub fn tick(&mut self, cycles: u32) {
-        for _ in 0..cycles {
-            self.tick_cycle();
+        if !self.enabled {
+            return;
+        }
+
+        let mut remaining = cycles;
+        while remaining > 0 {
+            let chunk = remaining.min(SAMPLE_PERIOD - self.sample_timer);
+            remaining -= chunk;
+
+            self.ch1.tick(chunk);
+            self.ch2.tick(chunk);
+            self.ch3.tick(chunk);
+            self.ch4.tick(chunk);
+
+            self.sample_timer += chunk;
+            if self.sample_timer >= SAMPLE_PERIOD {
+                self.sample_timer -= SAMPLE_PERIOD;
+
+                let (l, r) = self.mix_stereo();
+                self.sample_buffer_l.push(l.clamp(-1.0, 1.0));
+                self.sample_buffer_r.push(r.clamp(-1.0, 1.0));
+            }
+        }
+    }
+
+    /// Steps the frame sequencer, which is clocked by DIV bit 4 falling
+    pub fn step_frame_sequencer(&mut self) {
+        if !self.enabled {
+            return;
         }
+
+        self.frame_step = (self.frame_step + 1) & 7;
+        self.clock_frame_sequencer();
     }
 
     pub fn write_nr50(&mut self, value: u8) {
@@ -111,11 +143,12 @@ impl Apu {
     }
 
     pub fn write_nr52(&mut self, value: u8) {
-        self.enabled = value & 0x80 != 0;
+        let enabled = value & 0x80 != 0;
 
-        if !self.enabled {
-            self.reset();
+        if self.enabled && !enabled {
+            self.power_off();
         }
+        self.enabled = enabled;
     }
 
     pub fn read_nr52(&self) -> u8 {
@@ -126,42 +159,16 @@ impl Apu {
         (if self.ch1.enabled { 0x01 } else { 0 })
     }
 
+    /// Clears all registers and stops the channels. The length counters
+    /// keep their value on a DMG.
+    fn power_off(&mut self) {
+        self.ch1.power_off();
+        self.ch2.power_off();
+        self.ch3.power_off();
+        self.ch4.power_off();
 
-    fn tick_cycle(&mut self) {
-        if !self.enabled {
-            return;
-        }
-
-        self.cycles += 1;
-
-        self.frame_counter += 1;
-        if self.frame_counter >= 8192 {
-            self.frame_counter = 0;
-            self.frame_step = (self.frame_step + 1) & 7;
-            
-            self.clock_frame_sequencer();
-        }
-
-        self.ch1.tick();
-        self.ch2.tick();
-        self.ch3.tick();
-        self.ch4.tick();
-
-        self.sample_timer += 1;
-        if self.sample_timer >= SAMPLE_PERIOD {
-            self.sample_timer -= SAMPLE_PERIOD;
-        
-            let (l, r) = self.mix_stereo();
-            self.sample_buffer_l.push(l.clamp(-1.0, 1.0));
-            self.sample_buffer_r.push(r.clamp(-1.0, 1.0));
-        }
-    }
-
-    fn reset(&mut self) {
-        self.ch1.enabled = false;
-        self.ch2.enabled = false;
-        self.ch3.enabled = false;
-        self.ch4.enabled = false;
+        self.write_nr50(0x00);
+        self.write_nr51(0x00);
     }
 
     fn clock_frame_sequencer(&mut self) {
@@ -17,10 +17,16 @@ pub struct NoiseChannel {
     divisor_code: u8,
     width_mode_7: bool,
 
-    timer: i16,
+    timer: i32,
 }
 
 impl NoiseChannel {
+    /// Clears every register, only the length counter survives powering
+    /// the APU off
+    pub fn power_off(&mut self) {
+        *self = Self { length_counter: self.length_counter, ..Self::default() };
+    }
+
     pub fn write_nr41(&mut self, value: u8) {
         self.length_counter = 64 - (value & 0x3F);
     }
@@ -41,8 +47,8 @@ impl NoiseChannel {
         self.length_enable = value & 0x40 != 0;
     }
 
-    fn reload_timer(&self) -> i16 {
-        const DIVISORS: [i16; 8] = [8,16,32,48,64,80,96,112];
+    fn reload_timer(&self) -> i32 {
+        const DIVISORS: [i32; 8] = [8,16,32,48,64,80,96,112];
         DIVISORS[self.divisor_code as usize] << self.clock_shift
     }
 
@@ -59,14 +65,15 @@ impl NoiseChannel {
         self.timer = self.reload_timer();
     }
 
-    pub fn tick(&mut self) {
+    /// Runs the frequency timer for `cycles` T-cycles at once
+    pub fn tick(&mut self, cycles: u32) {
         if !self.enabled {
             return;
         }
 
-        self.timer -= 1;
-        if self.timer <= 0 {
-            self.timer = self.reload_timer();
+        self.timer -= cycles as i32;
+        while self.timer <= 0 {
+            self.timer += self.reload_timer();
 
             let bit0 = self.lfsr & 1;
             let bit1 = (self.lfsr >> 1) & 1;
@@ -12,7 +12,7 @@ pub struct SquareChannel1 {
     duty_step: u8,
 
     pub frequency: u16,
-    timer: i16,
+    timer: i32,
 
     pub length_counter: u8,
 
@@ -35,15 +35,19 @@ impl SquareChannel1 {
         self.dac_enabled = (value & 0xF8) != 0;
     }
 
-    pub fn tick(&mut self) {
+    /// Runs the frequency timer for `cycles` T-cycles at once
+    pub fn tick(&mut self, cycles: u32) {
         if !self.enabled {
             return;
         }
 
-        self.timer -= 1;
+        self.timer -= cycles as i32;
         if self.timer <= 0 {
-            self.timer = (2048 - self.frequency) as i16 * 4;
-            self.duty_step = (self.duty_step + 1) & 7;
+            let period = (2048 - self.frequency) as i32 * 4;
+            let steps = -self.timer / period + 1;
+
+            self.timer += steps * period;
+            self.duty_step = ((self.duty_step as i32 + steps) & 7) as u8;
         }
     }
 
@@ -68,6 +72,12 @@ impl SquareChannel1 {
         }
     }
 
+    /// Clears every register, only the length counter survives powering
+    /// the APU off
+    pub fn power_off(&mut self) {
+        *self = Self { length_counter: self.length_counter, ..Self::default() };
+    }
+
     pub fn write_envelope(&mut self, value: u8) {
         self.update_dac(value);
 
@@ -96,7 +106,7 @@ impl SquareChannel1 {
 
         self.enabled = true;
 
-        self.timer = (2048 - self.frequency) as i16 * 4;
+        self.timer = (2048 - self.frequency) as i32 * 4;
         self.duty_step = 0;
 
         if self.length_counter == 0 {
@@ -115,6 +125,13 @@ impl SquareChannel1 {
         }
     }
 
+    /// State the boot ROM leaves behind: the last note of the chime was
+    /// triggered on this channel and its envelope has faded out since
+    pub fn finish_boot_chime(&mut self) {
+        self.trigger();
+        self.volume = 0;
+    }
+
     pub fn clock_envelope(&mut self) {
         if self.envelope_period == 0 {
             return;
@@ -12,7 +12,7 @@ pub struct SquareChannel2 {
     duty_step: u8,
 
     frequency: u16,
-    timer: i16,
+    timer: i32,
 
     length_counter: u8,
 
@@ -28,15 +28,19 @@ impl SquareChannel2 {
         self.dac_enabled = (value & 0xF8) != 0;
     }
 
-    pub fn tick(&mut self) {
+    /// Runs the frequency timer for `cycles` T-cycles at once
+    pub fn tick(&mut self, cycles: u32) {
         if !self.enabled {
             return;
         }
 
-        self.timer -= 1;
+        self.timer -= cycles as i32;
         if self.timer <= 0 {
-            self.timer = (2048 - self.frequency) as i16 * 4;
-            self.duty_step = (self.duty_step + 1) & 7;
+            let period = (2048 - self.frequency) as i32 * 4;
+            let steps = -self.timer / period + 1;
+
+            self.timer += steps * period;
+            self.duty_step = ((self.duty_step as i32 + steps) & 7) as u8;
         }
     }
 
@@ -66,7 +70,7 @@ impl SquareChannel2 {
 
         self.enabled = true;
 
-        self.timer = (2048 - self.frequency) as i16 * 4;
+        self.timer = (2048 - self.frequency) as i32 * 4;
         self.duty_step = 0;
 
         if self.length_counter == 0 {
@@ -100,8 +104,18 @@ impl SquareChannel2 {
         }
     }
 
+    /// Clears every register, only the length counter survives powering
+    /// the APU off
+    pub fn power_off(&mut self) {
+        *self = Self { length_counter: self.length_counter, ..Self::default() };
+    }
+
     pub fn write_duty_length(&mut self, value: u8) {
         self.duty = value >> 6;
+        self.write_length(value);
+    }
+
+    pub fn write_length(&mut self, value: u8) {
         self.length_counter = 64 - (value & 0x3F);
     }
 
@@ -4,7 +4,7 @@ pub struct WaveChannel {
     dac_enabled: bool,
 
     frequency: u16,
-    timer: i16,
+    timer: i32,
 
     length_counter: u16,
 
@@ -15,6 +15,12 @@ pub struct WaveChannel {
 }
 
 impl WaveChannel {
+    /// Clears every register, only the length counter and wave RAM survive
+    /// powering the APU off
+    pub fn power_off(&mut self) {
+        *self = Self { length_counter: self.length_counter, wave_ram: self.wave_ram, ..Self::default() };
+    }
+
     pub fn write_nr30(&mut self, value: u8) {
         self.dac_enabled = value & 0x80 != 0;
         if self.dac_enabled { return; }
@@ -47,7 +53,7 @@ impl WaveChannel {
         }
 
         self.enabled = true;
-        self.timer = (2048 - self.frequency) as i16 * 2;
+        self.timer = (2048 - self.frequency) as i32 * 2;
         self.position = 0;
 
         if self.length_counter == 0 {
@@ -55,15 +61,19 @@ impl WaveChannel {
         }
     }
 
-    pub fn tick(&mut self) {
+    /// Runs the frequency timer for `cycles` T-cycles at once
+    pub fn tick(&mut self, cycles: u32) {
         if !self.enabled {
             return;
         }
 
-        self.timer -= 1;
+        self.timer -= cycles as i32;
         if self.timer <= 0 {
-            self.timer = (2048 - self.frequency) as i16 * 2;
-            self.position = (self.position + 1) & 31;
+            let period = (2048 - self.frequency) as i32 * 2;
+            let steps = -self.timer / period + 1;
+
+            self.timer += steps * period;
+            self.position = ((self.position as i32 + steps) & 31) as u8;
         }
     }
 
@@ -0,0 +1,114 @@
+use std::cell::Cell;
+
+use super::mmu::Mmu;
+
+/// Everything the CPU is connected to. Reads and writes take no time by
+/// themselves, the CPU calls `tick` for every M-cycle it spends.
+pub trait Bus {
+    fn read8(&self, addr: u16) -> u8;
+    fn write8(&mut self, addr: u16, value: u8);
+
+    /// Advances everything besides the CPU by `cycles`
+    fn tick(&mut self, cycles: u8);
+
+    /// Cycles until something may request an interrupt, `None` if nothing will.
+    /// A halted CPU skips ahead by that much.
+    fn cycles_until_event(&self) -> Option<u64> {
+        None
+    }
+
+    /// Requested and enabled interrupts (IE & IF). The CPU checks them
+    /// internally, this is no bus access.
+    fn pending_interrupts(&self) -> u8 {
+        self.read8(0xFFFF) & self.read8(0xFF0F) & 0x1F
+    }
+
+    /// Clears the IF bit of the interrupt the CPU dispatches, which
+    /// happens internally as well
+    fn acknowledge_interrupt(&mut self, interrupt: u8) {
+        let iflag = self.read8(0xFF0F);
+        self.write8(0xFF0F, iflag & !(1 << interrupt));
+    }
+}
+
+impl Bus for Mmu {
+    fn read8(&self, addr: u16) -> u8 {
+        Mmu::read8(self, addr)
+    }
+
+    fn write8(&mut self, addr: u16, value: u8) {
+        Mmu::write8(self, addr, value);
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        Mmu::tick(self, cycles);
+    }
+
+    fn cycles_until_event(&self) -> Option<u64> {
+        Mmu::cycles_until_event(self)
+    }
+}
+
+/// What the CPU did with the bus during one M-cycle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read(u16, u8),
+    Write(u16, u8),
+    /// An internal cycle without bus access
+    Idle,
+}
+
+/// 64 KiB of plain RAM without any I/O, for running the CPU on its own.
+/// Records one `Access` per M-cycle.
+pub struct FlatBus {
+    pub memory: Box<[u8; 0x10000]>,
+    pub accesses: Vec<Access>,
+    last_access: Cell<Option<Access>>,
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        Self {
+            memory: Box::new([0; 0x10000]),
+            accesses: Vec::new(),
+            last_access: Cell::new(None),
+        }
+    }
+}
+
+impl Bus for FlatBus {
+    fn read8(&self, addr: u16) -> u8 {
+        let value = self.memory[addr as usize];
+        self.last_access.set(Some(Access::Read(addr, value)));
+
+        value
+    }
+
+    fn write8(&mut self, addr: u16, value: u8) {
+        self.memory[addr as usize] = value;
+        self.last_access.set(Some(Access::Write(addr, value)));
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.memory[0xFFFF] & self.memory[0xFF0F] & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: u8) {
+        self.memory[0xFF0F] &= !(1 << interrupt);
+    }
+
+    /// Only the last access before a tick counts as the bus cycle
+    fn tick(&mut self, cycles: u8) {
+        for i in 0..cycles / 4 {
+            let access = if i == 0 { self.last_access.take() } else { None };
+            self.accesses.push(access.unwrap_or(Access::Idle));
+        }
+        self.last_access.set(None);
+    }
+}
@@ -0,0 +1,211 @@
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+pub mod rom_only;
+pub mod rtc;
+
+use crate::rom::{CartridgeHeader, RomError};
+
+use mbc1::Mbc1;
+use mbc2::Mbc2;
+use mbc3::Mbc3;
+use mbc5::Mbc5;
+use rom_only::RomOnly;
+use rtc::RtcClock;
+
+/// Bank switching logic of a cartridge (the Memory Bank Controller)
+pub trait Mapper {
+    /// The whole ROM image, regardless of what is mapped
+    fn rom(&self) -> &[u8];
+
+    /// The ROM bank currently mapped at `addr` in 0x0000-0x7FFF
+    fn rom_bank(&self, addr: u16) -> usize;
+
+    /// Reads from the ROM area 0x0000-0x7FFF
+    fn read_rom(&self, addr: u16) -> u8;
+
+    /// Handles writes to the ROM area 0x0000-0x7FFF, which on every
+    /// mapper are used to control its registers
+    fn write_register(&mut self, addr: u16, value: u8);
+
+    /// Reads from the external RAM area 0xA000-0xBFFF
+    fn read_ram(&self, addr: u16) -> u8;
+
+    /// Writes to the external RAM area 0xA000-0xBFFF
+    ///
+    /// * `result` - Returns true if RAM (or the clock) changed, false if the
+    ///   write was ignored or stored the same value
+    fn write_ram(&mut self, addr: u16, value: u8) -> bool;
+
+    /// State a battery would keep alive, in `.sav` file layout
+    fn save_data(&self) -> Vec<u8>;
+
+    /// Restores the state written by `save_data`
+    ///
+    /// * `result` - Returns false if `data` did not have the expected layout
+    fn load_save_data(&mut self, data: &[u8]) -> bool;
+
+    /// Advances cartridge hardware that runs on its own (e.g. the MBC3 RTC)
+    fn tick(&mut self, _cycles: u32) {}
+
+    /// Selects what the real-time clock counts, if the cartridge has one
+    fn set_rtc_clock(&mut self, _clock: RtcClock) {}
+
+    /// Whether the rumble motor is currently driven
+    fn rumble(&self) -> bool {
+        false
+    }
+}
+
+/// The inserted game: ROM, external RAM and the mapper controlling both
+pub struct Cartridge {
+    mapper: Box<dyn Mapper>,
+    header: CartridgeHeader,
+}
+
+impl Cartridge {
+    /// Picks the mapper from the cartridge type in header byte 0x0147
+    pub fn new(rom: Vec<u8>) -> Result<Self, RomError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        let cartridge_type = header.cartridge_type;
+        let ram_size = header.ram_size;
+
+        let mapper: Box<dyn Mapper> = match cartridge_type {
+            0x00 | 0x08 | 0x09 => Box::new(RomOnly::new(rom, ram_size)),
+            0x01..=0x03 => Box::new(Mbc1::new(rom, ram_size)),
+            0x05 | 0x06 => Box::new(Mbc2::new(rom)),
+            0x0F..=0x13 => Box::new(Mbc3::new(rom, ram_size, matches!(cartridge_type, 0x0F | 0x10))),
+            0x19..=0x1E => Box::new(Mbc5::new(rom, ram_size, matches!(cartridge_type, 0x1C..=0x1E))),
+            _ => return Err(RomError::UnsupportedCartridgeType(cartridge_type)),
+        };
+
+        Ok(Self {
+            mapper,
+            header,
+        })
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.header.has_battery()
+    }
+
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        self.mapper.read_rom(addr)
+    }
+
+    pub fn rom_bank(&self, addr: u16) -> usize {
+        self.mapper.rom_bank(addr)
+    }
+
+    /// Reads from ROM bank `bank` no matter which one is mapped, only the
+    /// lower 14 bits of `addr` count
+    pub fn read_rom_bank(&self, bank: usize, addr: u16) -> u8 {
+        let offset = bank * 0x4000 + (addr & 0x3FFF) as usize;
+        self.mapper.rom().get(offset).copied().unwrap_or(0xFF)
+    }
+
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        self.mapper.write_register(addr, value);
+    }
+
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        self.mapper.read_ram(addr)
+    }
+
+    /// * `result` - Returns true if the battery backed state changed
+    pub fn write_ram(&mut self, addr: u16, value: u8) -> bool {
+        self.mapper.write_ram(addr, value)
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.mapper.tick(cycles);
+    }
+
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.mapper.set_rtc_clock(clock);
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mapper.rumble()
+    }
+
+    /// Battery backed state in `.sav` layout, `None` if nothing survives power off
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.has_battery() {
+            return None;
+        }
+
+        Some(self.mapper.save_data()).filter(|data| !data.is_empty())
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) -> bool {
+        self.mapper.load_save_data(data)
+    }
+}
+
+/// Writes `value` to a RAM byte, if the address hit one
+///
+/// * `result` - Returns true if the byte changed
+fn store(byte: Option<&mut u8>, value: u8) -> bool {
+    match byte {
+        Some(byte) if *byte != value => {
+            *byte = value;
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Copies a save into external RAM, truncating or zero padding it
+///
+/// * `result` - Returns false if the size did not match
+fn load_ram(ram: &mut [u8], data: &[u8]) -> bool {
+    let len = data.len().min(ram.len());
+
+    ram[..len].copy_from_slice(&data[..len]);
+    ram[len..].fill(0);
+
+    data.len() == ram.len()
+}
+
+/// ROM with every bank filled with its own (truncated) bank number and
+/// the given cartridge type in the header
+#[cfg(test)]
+pub(super) fn test_rom(banks: usize, cartridge_type: u8) -> Vec<u8> {
+    let mut rom: Vec<u8> = (0..banks * 0x4000).map(|i| (i / 0x4000) as u8).collect();
+    rom[0x0147] = cartridge_type;
+    rom[0x0148] = banks.trailing_zeros().saturating_sub(1) as u8;
+    rom[0x0149] = 0;
+
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_cartridge_type_is_an_error() {
+        let result = Cartridge::new(test_rom(2, 0xFC));
+        assert!(matches!(result, Err(RomError::UnsupportedCartridgeType(0xFC))));
+    }
+
+    #[test]
+    fn ram_writes_report_changes() {
+        let mut rom = test_rom(2, 0x03);
+        rom[0x0149] = 0x02;
+        let mut cartridge = Cartridge::new(rom).unwrap();
+
+        // RAM is disabled after power on
+        assert!(!cartridge.write_ram(0xA000, 0x42));
+
+        cartridge.write_register(0x0000, 0x0A);
+        assert!(cartridge.write_ram(0xA000, 0x42));
+        assert!(!cartridge.write_ram(0xA000, 0x42));
+    }
+}
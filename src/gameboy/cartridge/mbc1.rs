@@ -0,0 +1,161 @@
+use super::{load_ram, store, Mapper};
+
+/// MBC1: up to 2 MiB ROM and 32 KiB RAM
+///
+/// The 2 bit `bank2` register either extends the ROM bank number (upper
+/// bits for both ROM windows in mode 1) or selects the RAM bank (mode 1
+/// only). MBC1M multicarts wire `bank1` with only 4 bits, so `bank2`
+/// selects one of four 256 KiB games.
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    bank1: u8,
+    bank2: u8,
+    ram_enabled: bool,
+
+    banking_mode: u8,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        let multicart = Self::is_multicart(&rom);
+
+        Self {
+            rom,
+            ram: vec![0; ram_size],
+
+            bank1: 1,
+            bank2: 0,
+            ram_enabled: false,
+
+            banking_mode: 0,
+            multicart,
+        }
+    }
+
+    /// MBC1M collections are 1 MiB carts where every 256 KiB game
+    /// starts with its own header, so bank 0x10 repeats the Nintendo logo
+    fn is_multicart(rom: &[u8]) -> bool {
+        const LOGO: std::ops::Range<usize> = 0x0104..0x0134;
+
+        if rom.len() != 64 * 0x4000 {
+            return false;
+        }
+
+        let second_game = 0x10 * 0x4000;
+        rom[LOGO] == rom[second_game + LOGO.start..second_game + LOGO.end]
+    }
+
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn rom_offset(&self, bank: usize, addr: u16) -> usize {
+        // The bank number is wired to as many address lines as the ROM has,
+        // so out of range banks wrap around
+        let bank_count = (self.rom.len() / 0x4000).max(1);
+        (bank % bank_count) * 0x4000 + (addr & 0x3FFF) as usize
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+
+        let bank = if self.banking_mode == 1 { self.bank2 as usize } else { 0 };
+        Some((bank * 0x2000 + (addr - 0xA000) as usize) % self.ram.len())
+    }
+}
+
+impl Mapper for Mbc1 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        let upper = (self.bank2 as usize) << self.bank2_shift();
+
+        let bank = match addr {
+            0x0000..=0x3FFF if self.banking_mode == 0 => 0,
+            0x0000..=0x3FFF => upper,
+            _ => {
+                let lower = if self.multicart { self.bank1 & 0x0F } else { self.bank1 };
+                upper | lower as usize
+            },
+        };
+
+        bank % (self.rom.len() / 0x4000).max(1)
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.rom.get(self.rom_offset(self.rom_bank(addr), addr)).copied().unwrap_or(0xFF)
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = (value & 0x0F) == 0x0A,
+            0x2000..=0x3FFF => {
+                // The zero check looks at all 5 bits, even on smaller ROMs
+                let bank = value & 0x1F;
+                self.bank1 = if bank == 0 { 1 } else { bank };
+            },
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.banking_mode = value & 0x01,
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled { return 0xFF; }
+
+        self.ram_offset(addr)
+            .map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) -> bool {
+        if !self.ram_enabled { return false; }
+
+        match self.ram_offset(addr) {
+            Some(offset) => store(self.ram.get_mut(offset), value),
+            None => false,
+        }
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) -> bool {
+        load_ram(&mut self.ram, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_rom;
+
+    #[test]
+    fn bank_0_maps_bank_1() {
+        let mut mbc = Mbc1::new(test_rom(4, 0x01), 0);
+
+        mbc.write_register(0x2000, 0x00);
+        assert_eq!(mbc.rom_bank(0x4000), 1);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        // The zero check sees all 5 bits, so 0x04 on a 4 bank ROM wraps to bank 0
+        mbc.write_register(0x2000, 0x04);
+        assert_eq!(mbc.read_rom(0x4000), 0);
+    }
+
+    #[test]
+    fn bank_0_maps_bank_1_in_upper_half() {
+        let mut mbc = Mbc1::new(test_rom(64, 0x01), 0);
+
+        mbc.write_register(0x4000, 0x01);
+        mbc.write_register(0x2000, 0x00);
+        assert_eq!(mbc.rom_bank(0x4000), 0x21);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+    }
+}
@@ -0,0 +1,147 @@
+use super::{load_ram, store, Mapper};
+
+/// Size of the RAM built into the MBC2 chip, each byte holds one nibble
+const RAM_SIZE: usize = 512;
+
+/// MBC2: up to 256 KiB ROM and 512x4 bit RAM inside the mapper itself
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    ram: [u8; RAM_SIZE],
+
+    rom_bank: u8,
+    ram_enabled: bool,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Self {
+            rom,
+            ram: [0; RAM_SIZE],
+
+            rom_bank: 1,
+            ram_enabled: false,
+        }
+    }
+
+    /// Only the lower 9 address bits are decoded, so the RAM
+    /// is echoed across the whole 0xA000-0xBFFF area
+    fn ram_offset(addr: u16) -> usize {
+        (addr & 0x01FF) as usize
+    }
+}
+
+impl Mapper for Mbc2 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+
+        bank % (self.rom.len() / 0x4000).max(1)
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        let offset = self.rom_bank(addr) * 0x4000 + (addr & 0x3FFF) as usize;
+
+        self.rom.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        // Both registers live in 0x0000-0x3FFF, address bit 8 selects which one
+        if addr > 0x3FFF { return; }
+
+        if addr & 0x0100 == 0 {
+            self.ram_enabled = (value & 0x0F) == 0x0A;
+        } else {
+            let bank = value & 0x0F;
+            self.rom_bank = if bank == 0 { 1 } else { bank };
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled { return 0xFF; }
+
+        // The upper nibble is not connected and reads as 1s
+        self.ram[Self::ram_offset(addr)] | 0xF0
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) -> bool {
+        if !self.ram_enabled { return false; }
+
+        store(self.ram.get_mut(Self::ram_offset(addr)), value & 0x0F)
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) -> bool {
+        let matches = load_ram(&mut self.ram, data);
+        for byte in self.ram.iter_mut() {
+            *byte &= 0x0F;
+        }
+
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_rom;
+
+    #[test]
+    fn address_bit_8_selects_the_register() {
+        let mut mbc = Mbc2::new(test_rom(16, 0x05));
+
+        // Bit 8 clear: RAM enable, the ROM bank stays
+        mbc.write_register(0x0000, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_ram(0xA000, 0x05);
+        assert_eq!(mbc.read_ram(0xA000), 0xF5);
+
+        // Bit 8 set: ROM bank, RAM stays enabled
+        mbc.write_register(0x2100, 0x03);
+        assert_eq!(mbc.read_rom(0x4000), 3);
+        mbc.write_register(0x3F00, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 10);
+        assert_eq!(mbc.read_ram(0xA000), 0xF5);
+
+        mbc.write_register(0x3E00, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        assert_eq!(mbc.read_rom(0x4000), 10);
+    }
+
+    #[test]
+    fn bank_0_maps_bank_1() {
+        let mut mbc = Mbc2::new(test_rom(16, 0x05));
+        mbc.write_register(0x2100, 0x05);
+        mbc.write_register(0x2100, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        // Only the lower nibble is used
+        mbc.write_register(0x2100, 0x10);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn ram_echoes_and_holds_nibbles() {
+        let mut mbc = Mbc2::new(test_rom(2, 0x06));
+        mbc.write_register(0x0000, 0x0A);
+
+        mbc.write_ram(0xA010, 0xAB);
+        assert_eq!(mbc.read_ram(0xA010), 0xFB);
+        for addr in [0xA210, 0xA410, 0xB010, 0xBE10] {
+            assert_eq!(mbc.read_ram(addr), 0xFB, "{:04X}", addr);
+        }
+
+        mbc.write_ram(0xBFFF, 0x03);
+        assert_eq!(mbc.read_ram(0xA1FF), 0xF3);
+        assert_eq!(mbc.save_data()[0x1FF], 0x03);
+    }
+}
@@ -0,0 +1,216 @@
+use super::{load_ram, store, rtc::{Rtc, RtcClock, RtcRegister}, Mapper};
+
+/// MBC3: up to 2 MiB ROM, 32 KiB RAM and an optional real-time clock
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    rom_bank: u8,
+    ram_bank: u8,
+    ram_enabled: bool,
+
+    rtc_select: Option<RtcRegister>,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> Self {
+        Self {
+            rom,
+            ram: vec![0; ram_size],
+
+            rom_bank: 1,
+            ram_bank: 0,
+            ram_enabled: false,
+
+            rtc_select: None,
+            rtc: if has_rtc { Some(Rtc::new()) } else { None },
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        self.ram_bank as usize * 0x2000 + (addr - 0xA000) as usize
+    }
+}
+
+impl Mapper for Mbc3 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom.get(addr as usize).copied().unwrap_or(0xFF),
+            _ => {
+                let bank_addr = (self.rom_bank as usize) * 0x4000 + ((addr - 0x4000) as usize);
+                self.rom.get(bank_addr).copied().unwrap_or(0xFF)
+            },
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = (value & 0x0F) == 0x0A,
+            0x2000..=0x3FFF => {
+                let mut bank = value & 0x7F;
+                if bank == 0 { bank = 1; }
+                self.rom_bank = bank;
+            },
+            0x4000..=0x5FFF => {
+                self.rtc_select = RtcRegister::from(value);
+                if self.rtc_select.is_none() {
+                    self.ram_bank = value & 0x03;
+                }
+            },
+            _ => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value);
+                }
+            },
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled { return 0xFF; }
+
+        match (&self.rtc_select, &self.rtc) {
+            (Some(reg), Some(rtc)) => rtc.read(reg),
+            (Some(_), None) => 0xFF,
+            _ => self.ram.get(self.ram_offset(addr)).copied().unwrap_or(0xFF),
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) -> bool {
+        if !self.ram_enabled { return false; }
+
+        match (&self.rtc_select, &mut self.rtc) {
+            (Some(reg), Some(rtc)) => rtc.write(reg, value),
+            (Some(_), None) => false,
+            _ => {
+                let offset = self.ram_offset(addr);
+                store(self.ram.get_mut(offset), value)
+            },
+        }
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend_from_slice(&rtc.save());
+        }
+
+        data
+    }
+
+    /// A missing or broken RTC trailer leaves the clock at its reset state
+    fn load_save_data(&mut self, data: &[u8]) -> bool {
+        let (ram, trailer) = data.split_at(data.len() - Rtc::trailer_len(data.len()));
+        let ram_ok = load_ram(&mut self.ram, ram);
+
+        let rtc_ok = match &mut self.rtc {
+            Some(rtc) => rtc.load(trailer),
+            None => trailer.is_empty(),
+        };
+
+        ram_ok && rtc_ok
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_clock(clock);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{rtc::CYCLES_PER_SECOND, test_rom};
+
+    #[test]
+    fn rtc_reads_the_latched_time() {
+        let mut mbc = Mbc3::new(test_rom(4, 0x10), 0, true);
+        mbc.set_rtc_clock(RtcClock::Emulated);
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_register(0x4000, 0x08);
+        mbc.write_ram(0xA000, 5);
+
+        mbc.tick(3 * CYCLES_PER_SECOND);
+        assert_eq!(mbc.read_ram(0xA000), 5);
+
+        mbc.write_register(0x6000, 0x00);
+        mbc.write_register(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 8);
+
+        // Only a 0 -> 1 sequence latches
+        mbc.tick(CYCLES_PER_SECOND);
+        mbc.write_register(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 8);
+    }
+
+    #[test]
+    fn halted_rtc_stands_still() {
+        let mut mbc = Mbc3::new(test_rom(4, 0x10), 0, true);
+        mbc.set_rtc_clock(RtcClock::Emulated);
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_register(0x4000, 0x0C);
+        mbc.write_ram(0xA000, 0x40);
+
+        mbc.tick(2 * CYCLES_PER_SECOND);
+        mbc.write_register(0x6000, 0x00);
+        mbc.write_register(0x6000, 0x01);
+        mbc.write_register(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(0xA000), 0);
+    }
+
+    #[test]
+    fn rtc_writes_report_changes() {
+        let mut mbc = Mbc3::new(test_rom(4, 0x10), 0, true);
+        mbc.set_rtc_clock(RtcClock::Emulated);
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_register(0x4000, 0x09);
+
+        assert!(mbc.write_ram(0xA000, 12));
+        assert!(!mbc.write_ram(0xA000, 12));
+        // Only the lower 6 bits are stored
+        assert!(!mbc.write_ram(0xA000, 0x40 | 12));
+    }
+
+    #[test]
+    fn rtc_trailer_is_found_with_a_different_ram_size() {
+        let mut mbc = Mbc3::new(test_rom(4, 0x10), 0x2000, true);
+        mbc.set_rtc_clock(RtcClock::Emulated);
+        mbc.write_register(0x0000, 0x0A);
+        mbc.write_register(0x4000, 0x0A);
+        mbc.write_ram(0xA000, 7);
+        let data = mbc.save_data();
+        assert_eq!(data.len(), 0x2000 + 48);
+
+        for trailer in [48, 44] {
+            let mut loaded = Mbc3::new(test_rom(4, 0x10), 0x8000, true);
+            loaded.set_rtc_clock(RtcClock::Emulated);
+            let data = &data[..0x2000 + trailer];
+
+            // The RAM size differs, the clock is restored anyway
+            assert!(!loaded.load_save_data(data));
+            loaded.write_register(0x0000, 0x0A);
+            loaded.write_register(0x6000, 0x00);
+            loaded.write_register(0x6000, 0x01);
+            loaded.write_register(0x4000, 0x0A);
+            assert_eq!(loaded.read_ram(0xA000), 7);
+        }
+    }
+}
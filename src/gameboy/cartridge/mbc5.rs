@@ -0,0 +1,123 @@
+use super::{load_ram, store, Mapper};
+
+/// MBC5: up to 8 MiB ROM, 128 KiB RAM and on some carts a rumble motor
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    rom_bank: u16,
+    ram_bank: u8,
+    ram_enabled: bool,
+
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self {
+        Self {
+            rom,
+            ram: vec![0; ram_size],
+
+            rom_bank: 1,
+            ram_bank: 0,
+            ram_enabled: false,
+
+            has_rumble,
+            rumble: false,
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        self.ram_bank as usize * 0x2000 + (addr - 0xA000) as usize
+    }
+}
+
+impl Mapper for Mbc5 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        };
+
+        // Bank numbers wrap around at the actual ROM size
+        bank % (self.rom.len() / 0x4000).max(1)
+    }
+
+    fn read_rom(&self, addr: u16) -> u8 {
+        let offset = self.rom_bank(addr) * 0x4000 + (addr & 0x3FFF) as usize;
+
+        self.rom.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = (value & 0x0F) == 0x0A,
+            // Unlike MBC1/MBC3, bank 0 can be mapped into 0x4000-0x7FFF
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (((value & 0x01) as u16) << 8),
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    // Bit 3 drives the motor, leaving 8 RAM banks
+                    self.rumble = value & 0x08 != 0;
+                    self.ram_bank = value & 0x07;
+                } else {
+                    self.ram_bank = value & 0x0F;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled { return 0xFF; }
+
+        self.ram.get(self.ram_offset(addr)).copied().unwrap_or(0xFF)
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) -> bool {
+        if !self.ram_enabled { return false; }
+
+        let offset = self.ram_offset(addr);
+        store(self.ram.get_mut(offset), value)
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) -> bool {
+        load_ram(&mut self.ram, data)
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_rom;
+
+    #[test]
+    fn rom_bank_has_9_bits() {
+        let mut mbc = Mbc5::new(test_rom(512, 0x19), 0, false);
+
+        mbc.write_register(0x2000, 0xFF);
+        mbc.write_register(0x3000, 0x01);
+        assert_eq!(mbc.rom_bank(0x4000), 0x1FF);
+        assert_eq!(mbc.read_rom(0x4000), 0xFF);
+
+        mbc.write_register(0x2000, 0x00);
+        assert_eq!(mbc.rom_bank(0x4000), 0x100);
+
+        // Bank 0 is allowed in the switchable area
+        mbc.write_register(0x3000, 0x00);
+        assert_eq!(mbc.rom_bank(0x4000), 0);
+    }
+}
@@ -0,0 +1,420 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Size of the RTC trailer VBA and BGB append to the `.sav` file
+pub const RTC_SAVE_SIZE: usize = 48;
+/// Older VBA versions store the timestamp as 32 bit value
+const RTC_SAVE_SIZE_LEGACY: usize = 44;
+
+pub(super) const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+#[derive(Clone, Copy)]
+pub enum RtcRegister {
+    Seconds = 0x08,
+    Minutes = 0x09,
+    Hours = 0x0A,
+    DayLow = 0x0B,
+    DayHigh = 0x0C,
+}
+
+impl RtcRegister {
+    /// Order in which the registers are stored in the save trailer
+    const ALL: [RtcRegister; 5] = [
+        RtcRegister::Seconds,
+        RtcRegister::Minutes,
+        RtcRegister::Hours,
+        RtcRegister::DayLow,
+        RtcRegister::DayHigh,
+    ];
+
+    pub fn from(reg: u8) -> Option<RtcRegister> {
+        match reg {
+            0x08 => Some(RtcRegister::Seconds),
+            0x09 => Some(RtcRegister::Minutes),
+            0x0A => Some(RtcRegister::Hours),
+            0x0B => Some(RtcRegister::DayLow),
+            0x0C => Some(RtcRegister::DayHigh),
+            _ => None,
+        }
+    }
+}
+
+/// Counter values as seen through the five RTC registers
+#[derive(Clone, Copy, Default)]
+struct RtcCounters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halt: bool,
+    day_carry: bool,
+}
+
+impl RtcCounters {
+    fn read(&self, reg: &RtcRegister) -> u8 {
+        match reg {
+            RtcRegister::Seconds => self.seconds,
+            RtcRegister::Minutes => self.minutes,
+            RtcRegister::Hours   => self.hours,
+            RtcRegister::DayLow  => self.days as u8,
+            RtcRegister::DayHigh => {
+                ((self.days >> 8) as u8 & 0x01) |
+                (if self.halt      { 0x40 } else { 0 }) |
+                (if self.day_carry { 0x80 } else { 0 })
+            },
+        }
+    }
+
+    fn write(&mut self, reg: &RtcRegister, value: u8) {
+        match reg {
+            RtcRegister::Seconds => self.seconds = value & 0x3F,
+            RtcRegister::Minutes => self.minutes = value & 0x3F,
+            RtcRegister::Hours   => self.hours = value & 0x1F,
+            RtcRegister::DayLow  => self.days = (self.days & 0x100) | value as u16,
+            RtcRegister::DayHigh => {
+                self.days = (self.days & 0xFF) | (((value & 0x01) as u16) << 8);
+                self.halt = value & 0x40 != 0;
+                self.day_carry = value & 0x80 != 0;
+            },
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    /// Advances by one second, including the hardware behaviour for
+    /// out of range values (they count up to the bit width and wrap
+    /// to 0 without carrying into the next counter)
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 { return; }
+
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 { return; }
+
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 { return; }
+
+        self.hours = 0;
+        self.tick_days(1);
+    }
+
+    fn tick_days(&mut self, days: u64) {
+        let days = self.days as u64 + days;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (days & 0x1FF) as u16;
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        while seconds > 0 && !self.is_valid() {
+            self.tick_second();
+            seconds -= 1;
+        }
+
+        let total = seconds
+            + self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600;
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        self.tick_days(total / 86400);
+    }
+
+    fn save(&self, out: &mut Vec<u8>) {
+        for reg in RtcRegister::ALL {
+            out.extend_from_slice(&(self.read(&reg) as u32).to_le_bytes());
+        }
+    }
+
+    fn load(&mut self, data: &[u8]) {
+        for (i, reg) in RtcRegister::ALL.iter().enumerate() {
+            self.write(reg, data[i * 4]);
+        }
+    }
+}
+
+/// What the RTC counts
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RtcClock {
+    /// Follows the host clock, so like on the real cartridge the time keeps
+    /// running while the game is paused or the emulator is closed
+    #[default]
+    Host,
+    /// Counts emulated cycles, so it is deterministic and runs with the
+    /// emulation speed. The time the emulator was closed is still caught
+    /// up from the host clock when a save is loaded.
+    Emulated,
+}
+
+impl RtcClock {
+    pub fn from_name(name: &str) -> Option<RtcClock> {
+        match &name.to_ascii_lowercase()[..] {
+            "host" => Some(RtcClock::Host),
+            "emulated" => Some(RtcClock::Emulated),
+            _ => None,
+        }
+    }
+}
+
+/// MBC3 real-time clock
+///
+/// With the host clock `counters` hold the time at `base`, the current
+/// time is computed from the host time passed since. Writes and a halt
+/// fold the passed time into `counters` and move `base`.
+pub struct Rtc {
+    clock: RtcClock,
+    counters: RtcCounters,
+    base: Duration,
+    latched: RtcCounters,
+    last_latch_write: u8,
+    /// Cycles since the last full second, only counted with `RtcClock::Emulated`
+    cycles: u32,
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            clock: RtcClock::default(),
+            counters: RtcCounters::default(),
+            base: Self::now(),
+            latched: RtcCounters::default(),
+            last_latch_write: 0xFF,
+            cycles: 0,
+        }
+    }
+
+    /// Length of the RTC trailer at the end of a save of `save_len` bytes.
+    /// RAM sizes are multiples of 512 bytes, so whatever is left over is
+    /// the trailer.
+    pub fn trailer_len(save_len: usize) -> usize {
+        match save_len % 0x200 {
+            len @ (RTC_SAVE_SIZE | RTC_SAVE_SIZE_LEGACY) => len,
+            _ => 0,
+        }
+    }
+
+    fn now() -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.sync(Self::now());
+        self.clock = clock;
+        self.base = Self::now();
+        self.cycles = 0;
+    }
+
+    /// Counters as they are at host time `now`
+    fn current(&self, now: Duration) -> RtcCounters {
+        let mut counters = self.counters;
+        if self.clock == RtcClock::Host && !counters.halt {
+            counters.advance(now.saturating_sub(self.base).as_secs());
+        }
+
+        counters
+    }
+
+    /// Folds the full seconds passed until `now` into `counters`, a halted
+    /// clock drops the passed time instead
+    fn sync(&mut self, now: Duration) {
+        if self.clock != RtcClock::Host { return; }
+
+        if self.counters.halt {
+            self.base = now;
+        } else {
+            let seconds = now.saturating_sub(self.base).as_secs();
+            self.counters.advance(seconds);
+            self.base += Duration::from_secs(seconds);
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if self.clock != RtcClock::Emulated || self.counters.halt {
+            return;
+        }
+
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.counters.tick_second();
+        }
+    }
+
+    /// Reads the latched copy of a register
+    pub fn read(&self, reg: &RtcRegister) -> u8 {
+        self.latched.read(reg)
+    }
+
+    /// * `result` - Returns true if the register changed
+    pub fn write(&mut self, reg: &RtcRegister, value: u8) -> bool {
+        self.write_at(reg, value, Self::now())
+    }
+
+    fn write_at(&mut self, reg: &RtcRegister, value: u8, now: Duration) -> bool {
+        self.sync(now);
+
+        // Writing the seconds also resets the sub-second divider
+        if let RtcRegister::Seconds = reg {
+            self.cycles = 0;
+            self.base = now;
+        }
+
+        let old = self.counters.read(reg);
+        self.counters.write(reg, value);
+        self.latched.write(reg, value);
+
+        self.counters.read(reg) != old
+    }
+
+    /// Writes to 0x6000-0x7FFF: writing 0x00 followed by 0x01 copies the
+    /// running counters into the latched registers
+    pub fn write_latch(&mut self, value: u8) {
+        self.write_latch_at(value, Self::now());
+    }
+
+    fn write_latch_at(&mut self, value: u8, now: Duration) {
+        if self.last_latch_write == 0x00 && value == 0x01 {
+            self.latched = self.current(now);
+        }
+
+        self.last_latch_write = value;
+    }
+
+    /// Serializes into the 48 byte VBA/BGB trailer
+    pub fn save(&self) -> Vec<u8> {
+        self.save_at(Self::now())
+    }
+
+    fn save_at(&self, now: Duration) -> Vec<u8> {
+        let mut out = Vec::with_capacity(RTC_SAVE_SIZE);
+        self.current(now).save(&mut out);
+        self.latched.save(&mut out);
+        out.extend_from_slice(&now.as_secs().to_le_bytes());
+
+        out
+    }
+
+    /// Restores from a VBA/BGB trailer and advances the clock by the host
+    /// time that passed since it was written
+    ///
+    /// * `result` - Returns false if `data` is no valid trailer
+    pub fn load(&mut self, data: &[u8]) -> bool {
+        self.load_at(data, Self::now())
+    }
+
+    fn load_at(&mut self, data: &[u8], now: Duration) -> bool {
+        let timestamp = match data.len() {
+            RTC_SAVE_SIZE => u64::from_le_bytes(data[40..48].try_into().unwrap_or_default()),
+            RTC_SAVE_SIZE_LEGACY => u32::from_le_bytes(data[40..44].try_into().unwrap_or_default()) as u64,
+            _ => return false,
+        };
+
+        self.counters.load(&data[0..20]);
+        self.latched.load(&data[20..40]);
+        self.cycles = 0;
+
+        // The counters were current at the time the save was written
+        self.base = Duration::from_secs(timestamp);
+        self.sync(now);
+        if self.clock == RtcClock::Emulated && !self.counters.halt {
+            self.counters.advance(now.as_secs().saturating_sub(timestamp));
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    fn latch(rtc: &mut Rtc, now: Duration) {
+        rtc.write_latch_at(0x00, now);
+        rtc.write_latch_at(0x01, now);
+    }
+
+    fn host_rtc(now: Duration) -> Rtc {
+        let mut rtc = Rtc::new();
+        rtc.base = now;
+        rtc
+    }
+
+    #[test]
+    fn host_clock_counts_from_the_base() {
+        let mut rtc = host_rtc(secs(1000));
+
+        latch(&mut rtc, secs(1000) + Duration::from_millis(999));
+        assert_eq!(rtc.read(&RtcRegister::Seconds), 0);
+
+        latch(&mut rtc, secs(1000 + 3 * 3600 + 2 * 60 + 1));
+        assert_eq!(rtc.read(&RtcRegister::Seconds), 1);
+        assert_eq!(rtc.read(&RtcRegister::Minutes), 2);
+        assert_eq!(rtc.read(&RtcRegister::Hours), 3);
+
+        // Emulated cycles do not count
+        rtc.tick(10 * CYCLES_PER_SECOND);
+        latch(&mut rtc, secs(1000 + 3 * 3600 + 2 * 60 + 1));
+        assert_eq!(rtc.read(&RtcRegister::Seconds), 1);
+    }
+
+    #[test]
+    fn halt_freezes_the_host_clock() {
+        let mut rtc = host_rtc(secs(1000));
+
+        rtc.write_at(&RtcRegister::DayHigh, 0x40, secs(1005));
+        latch(&mut rtc, secs(2000));
+        assert_eq!(rtc.read(&RtcRegister::Seconds), 5);
+
+        // Running again continues from the frozen time
+        rtc.write_at(&RtcRegister::DayHigh, 0x00, secs(2000));
+        latch(&mut rtc, secs(2010));
+        assert_eq!(rtc.read(&RtcRegister::Seconds), 15);
+    }
+
+    #[test]
+    fn seconds_write_resets_the_sub_second_part() {
+        let mut rtc = host_rtc(secs(1000));
+
+        rtc.write_at(&RtcRegister::Seconds, 30, secs(1000) + Duration::from_millis(600));
+        latch(&mut rtc, secs(1001) + Duration::from_millis(500));
+        assert_eq!(rtc.read(&RtcRegister::Seconds), 30);
+
+        latch(&mut rtc, secs(1001) + Duration::from_millis(600));
+        assert_eq!(rtc.read(&RtcRegister::Seconds), 31);
+    }
+
+    #[test]
+    fn load_catches_up_from_the_save_timestamp() {
+        let mut rtc = host_rtc(secs(1000));
+        rtc.write_at(&RtcRegister::Hours, 23, secs(1000));
+        let data = rtc.save_at(secs(1000));
+
+        for clock in [RtcClock::Host, RtcClock::Emulated] {
+            let mut loaded = Rtc::new();
+            loaded.clock = clock;
+            assert!(loaded.load_at(&data, secs(1000 + 3600)));
+
+            latch(&mut loaded, secs(1000 + 3600));
+            assert_eq!(loaded.read(&RtcRegister::Hours), 0);
+            assert_eq!(loaded.read(&RtcRegister::DayLow), 1);
+        }
+    }
+}
@@ -1,8 +1,11 @@
-mod registers;
+pub mod registers;
 
-use super::mmu::Mmu;
+use super::{bus::Bus, model::Model};
+use crate::rom::CartridgeHeader;
 use registers::{Flags, Reg8, Reg16, Registers};
 
+/// Most cycles a halted CPU skips ahead in one step
+const MAX_HALT_SKIP: u8 = 252;
 
 pub enum Interrupt {
     VBlank,
@@ -29,6 +32,18 @@ pub struct Cpu {
 
     pub stopped: bool,
     pub halt_bug: bool,
+
+    /// Set by `LD B, B`, which test ROMs and debuggers use as a software breakpoint
+    pub breakpoint: bool,
+
+    /// Cycles the current instruction has already advanced the system by
+    elapsed: u8,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
@@ -42,40 +57,83 @@ impl Cpu {
             halted: false,
             stopped: false,
             halt_bug: false,
+            breakpoint: false,
+            elapsed: 0,
+        }
+    }
+
+    /// CPU state the boot ROM of `model` leaves behind when jumping to 0x0100
+    pub fn post_boot(model: Model, header: &CartridgeHeader) -> Self {
+        let mut cpu = Self::new();
+
+        let [af, bc, de, hl] = model.boot_registers(header);
+        cpu.registers.write16(&Reg16::AF, af);
+        cpu.registers.write16(&Reg16::BC, bc);
+        cpu.registers.write16(&Reg16::DE, de);
+        cpu.registers.write16(&Reg16::HL, hl);
+
+        cpu
+    }
+
+    /// CPU state right after power on, executing the boot ROM from 0x0000
+    pub fn power_on() -> Self {
+        Self {
+            stack_pointer: 0x0000,
+            program_counter: 0x0000,
+            registers: Registers::power_on(),
+            pending_ime: false,
+            ime: false,
+            halted: false,
+            stopped: false,
+            halt_bug: false,
+            breakpoint: false,
+            elapsed: 0,
         }
     }
 
     /// Steps 
     /// 
     /// * `result` - Returns the cycles the cpu needs to execute the current opcode
-    pub fn step(&mut self, mmu: &mut Mmu) -> u8 {
-        let interrupt_cycles = self.handle_interrupts(mmu);
+    pub fn step(&mut self, bus: &mut impl Bus) -> u8 {
+        let cycles = self.execute(bus);
+
+        // Cycles not spent on bus accesses are internal ones at the end of
+        // the instruction, nothing else touches the bus in between
+        if self.elapsed < cycles {
+            bus.tick(cycles - self.elapsed);
+        }
+        self.elapsed = 0;
+
+        cycles
+    }
+
+    fn execute(&mut self, bus: &mut impl Bus) -> u8 {
+        let interrupt_cycles = self.handle_interrupts(bus);
         if interrupt_cycles > 0 {
             return interrupt_cycles;
         }
 
         if self.stopped {
-            let ie = mmu.read8(0xFFFF) & 0x1F;
-            let iflag = mmu.read8(0xFF0F) & 0x1F;
-            
-            if (ie & iflag) != 0 {
+            if bus.pending_interrupts() != 0 {
                 self.stopped = false;
             }
             return 4;
         }
 
         if self.halted {
-            let ie = mmu.read8(0xFFFF) & 0x1F;
-            let iflag = mmu.read8(0xFF0F) & 0x1F;
-
-            if (ie & iflag) != 0 {
+            if bus.pending_interrupts() != 0 {
                 self.halted = false;
+                return 4;
             }
-            return 4;
+
+            // Only a scheduled event can request an interrupt, so skip
+            // ahead to the next one in whole M-cycles
+            let until_event = bus.cycles_until_event().unwrap_or(u64::MAX).min(MAX_HALT_SKIP as u64);
+            return (until_event as u8).next_multiple_of(4).max(4);
         }
 
         let mut executed_ei = false;
-        let opcode = self.fetch_byte(mmu);
+        let opcode = self.fetch_byte(bus);
         let cycles = match opcode {
             // NOP aka No OPeration
             0x00 => 4,
@@ -101,9 +159,10 @@ impl Cpu {
 
             //LD [a16] SP
             0x08 => {
-                let addr = self.fetch_word(mmu);
+                let addr = self.fetch_word(bus);
 
-                mmu.write16(addr, self.stack_pointer);
+                self.write(bus, addr, self.stack_pointer as u8);
+                self.write(bus, addr.wrapping_add(1), (self.stack_pointer >> 8) as u8);
 
                 20
             },
@@ -168,7 +227,7 @@ impl Cpu {
             },
 
             0x10 => {
-                self.fetch_byte(mmu);
+                self.fetch_byte(bus);
                 self.stopped = true;
 
                 4
@@ -192,7 +251,7 @@ impl Cpu {
                     _ => unreachable!()
                 };
                 
-                let jmp_offset = self.fetch_byte(mmu) as i8;
+                let jmp_offset = self.fetch_byte(bus) as i8;
 
                 if condition {
                     self.program_counter = self.program_counter.wrapping_add_signed(jmp_offset as i16);
@@ -280,7 +339,7 @@ impl Cpu {
 
                 let val = match destination {
                     Operand8::Register(ref reg) => self.registers.read8(reg),
-                    Operand8::IndirectHL => mmu.read8(self.registers.read16(&Reg16::HL)),
+                    Operand8::IndirectHL => self.read(bus, self.registers.read16(&Reg16::HL)),
                 };
 
                 let is_inc = (opcode & 0x01) == 0;
@@ -291,7 +350,7 @@ impl Cpu {
 
                 match destination {
                     Operand8::Register(ref reg) => self.registers.write8(reg, result),
-                    Operand8::IndirectHL => mmu.write8(self.registers.read16(&Reg16::HL), result),
+                    Operand8::IndirectHL => self.write(bus, self.registers.read16(&Reg16::HL), result),
                 }
 
                 let flags = &mut self.registers.flag_register;
@@ -346,10 +405,10 @@ impl Cpu {
                     _ => panic!("unsupported operation on {:02X}", opcode),
                 };
 
-                let val = self.fetch_byte(mmu);
+                let val = self.fetch_byte(bus);
 
                 match operation {
-                    Operand8::IndirectHL => mmu.write8(self.registers.read16(&Reg16::HL), val),
+                    Operand8::IndirectHL => self.write(bus, self.registers.read16(&Reg16::HL), val),
                     Operand8::Register(reg) => self.registers.write8(&reg, val)
                 }
                 
@@ -358,17 +417,15 @@ impl Cpu {
 
             //LD HL n8
             0x36 => {
-                let val = self.fetch_byte(mmu);
-                 mmu.write8(self.registers.read16(&Reg16::HL), val);
+                let val = self.fetch_byte(bus);
+                 self.write(bus, self.registers.read16(&Reg16::HL), val);
                 
                 12
             }
 
             //HALT
             0x76 => {
-                let ie = mmu.read8(0xFFFF) & 0x1F;
-                let iflag = mmu.read8(0xFF0F) & 0x1F;
-                let pending = (ie & iflag) != 0;
+                let pending = bus.pending_interrupts() != 0;
 
                 if !self.ime && pending {
                     self.halt_bug = true;
@@ -383,7 +440,7 @@ impl Cpu {
             0x46 | 0x4E | 0x56 | 0x5E | 0x66 | 0x6E | 0x7E => {
                 let dst = Reg8::from((opcode >> 3) & 0x07);
 
-                let val = mmu.read8(self.registers.read16(&Reg16::HL));
+                let val = self.read(bus, self.registers.read16(&Reg16::HL));
                 self.registers.write8(&dst, val);
 
                 8
@@ -394,7 +451,7 @@ impl Cpu {
                 let src = Reg8::from(opcode & 0x07);
 
                 let val = self.registers.read8(&src);
-                mmu.write8(self.registers.read16(&Reg16::HL), val);
+                self.write(bus, self.registers.read16(&Reg16::HL), val);
 
                 8
             },
@@ -407,6 +464,10 @@ impl Cpu {
                 let val = self.registers.read8(&src);
                 self.registers.write8(&dst, val);
 
+                if opcode == 0x40 {
+                    self.breakpoint = true;
+                }
+
                 4
             },
 
@@ -414,7 +475,7 @@ impl Cpu {
             0x01 | 0x11 | 0x21 => {
                 let reg16 = Reg16::from((opcode >> 4) & 0x03);
 
-                let word = self.fetch_word(mmu);
+                let word = self.fetch_word(bus);
                 self.registers.write16(&reg16, word);
 
                 12
@@ -422,7 +483,7 @@ impl Cpu {
 
             //LD SP n16
             0x31 => {
-                let word = self.fetch_word(mmu);
+                let word = self.fetch_word(bus);
                 self.stack_pointer = word;
 
                 12
@@ -435,7 +496,7 @@ impl Cpu {
                 let reg16 = Reg16::from((opcode >> 4) & 0x03);
                 let addr = self.registers.read16(&reg16);
 
-                mmu.write8(addr, val);
+                self.write(bus, addr, val);
 
                 8
             },
@@ -446,7 +507,7 @@ impl Cpu {
 
                 let addr = self.registers.read16(&Reg16::HL);
 
-                mmu.write8(addr, val);
+                self.write(bus, addr, val);
 
                 let write_back = if opcode == 0x22 { addr.wrapping_add(1) } else { addr.wrapping_sub(1) };
                 self.registers.write16(&Reg16::HL, write_back);
@@ -459,7 +520,7 @@ impl Cpu {
                 let reg16 = Reg16::from((opcode >> 4) & 0x03);
                 let addr = self.registers.read16(&reg16);
 
-                let val = mmu.read8(addr);
+                let val = self.read(bus, addr);
 
                 self.registers.write8(&Reg8::A, val);
 
@@ -470,7 +531,7 @@ impl Cpu {
             0x2A | 0x3A => {
                 let addr = self.registers.read16(&Reg16::HL);
 
-                let val = mmu.read8(addr);
+                let val = self.read(bus, addr);
 
                 self.registers.write8(&Reg8::A, val);
 
@@ -482,22 +543,22 @@ impl Cpu {
 
             //LDH [a8] A
             0xE0 => {
-                let a8 = self.fetch_byte(mmu);
+                let a8 = self.fetch_byte(bus);
                 let addr = 0xFF00 | (a8 as u16);
 
                 let val = self.registers.read8(&Reg8::A);
 
-                mmu.write8(addr, val);
+                self.write(bus, addr, val);
 
                 12
             },
 
             //LDH A [a8]
             0xF0 => {
-                let a8 = self.fetch_byte(mmu);
+                let a8 = self.fetch_byte(bus);
                 let addr = 0xFF00 | (a8 as u16);
 
-                let val = mmu.read8(addr);
+                let val = self.read(bus, addr);
 
                 self.registers.write8(&Reg8::A, val);
 
@@ -511,7 +572,7 @@ impl Cpu {
 
                 let val = self.registers.read8(&Reg8::A);
 
-                mmu.write8(addr, val);
+                self.write(bus, addr, val);
 
                 8
             },
@@ -521,7 +582,7 @@ impl Cpu {
                 let reg_c_val = self.registers.read8(&Reg8::C);
                 let addr = 0xFF00 | (reg_c_val as u16);
 
-                let val = mmu.read8(addr);
+                let val = self.read(bus, addr);
 
                 self.registers.write8(&Reg8::A, val);
                 
@@ -530,20 +591,20 @@ impl Cpu {
 
             //LD [a16] A
             0xEA => {
-                let addr = self.fetch_word(mmu);
+                let addr = self.fetch_word(bus);
 
                 let val = self.registers.read8(&Reg8::A);
 
-                mmu.write8(addr, val);
+                self.write(bus, addr, val);
                 
                 16
             },
 
             //LD A [a16]
             0xFA => {
-                let addr = self.fetch_word(mmu);
+                let addr = self.fetch_word(bus);
 
-                let val = mmu.read8(addr);
+                let val = self.read(bus, addr);
 
                 self.registers.write8(&Reg8::A, val);
 
@@ -552,7 +613,7 @@ impl Cpu {
 
             //ADD SP, e8
             0xE8 => {
-                let e8 = self.fetch_byte(mmu) as i8 as i16;
+                let e8 = self.fetch_byte(bus) as i8 as i16;
                 let sp = self.stack_pointer;
             
                 let result = sp.wrapping_add(e8 as u16);
@@ -572,7 +633,7 @@ impl Cpu {
 
             // LD HL, SP+e8
             0xF8 => {
-                let e8 = self.fetch_byte(mmu) as i8;
+                let e8 = self.fetch_byte(bus) as i8;
                 let sp = self.stack_pointer;
             
                 let sp_low = sp & 0xFF;
@@ -607,8 +668,8 @@ impl Cpu {
                 let reg_num = opcode & 0x07;
 
                 let to_add_value = match opcode {
-                    0x86 => mmu.read8(self.registers.read16(&Reg16::HL)),
-                    0xC6 => self.fetch_byte(mmu),
+                    0x86 => self.read(bus, self.registers.read16(&Reg16::HL)),
+                    0xC6 => self.fetch_byte(bus),
                     _ => self.registers.read8(&Reg8::from(reg_num))
                 };
                 
@@ -632,8 +693,8 @@ impl Cpu {
                 let reg_num = opcode & 0x07;
 
                 let to_add_value = match opcode {
-                    0x8E => mmu.read8(self.registers.read16(&Reg16::HL)),
-                    0xCE => self.fetch_byte(mmu),
+                    0x8E => self.read(bus, self.registers.read16(&Reg16::HL)),
+                    0xCE => self.fetch_byte(bus),
                     _ => self.registers.read8(&Reg8::from(reg_num))
                 };
 
@@ -661,8 +722,8 @@ impl Cpu {
                 let reg_num = opcode & 0x07;
 
                 let to_sub_value = match opcode {
-                    0x9E => mmu.read8(self.registers.read16(&Reg16::HL)),
-                    0xDE => self.fetch_byte(mmu),
+                    0x9E => self.read(bus, self.registers.read16(&Reg16::HL)),
+                    0xDE => self.fetch_byte(bus),
                     _ => self.registers.read8(&Reg8::from(reg_num))
                 };
 
@@ -711,8 +772,8 @@ impl Cpu {
                 let reg_num = opcode & 0x07;
 
                 let to_sub_value = match opcode {
-                    0x96 | 0xBE => mmu.read8(self.registers.read16(&Reg16::HL)),
-                    0xD6 | 0xFE => self.fetch_byte(mmu),
+                    0x96 | 0xBE => self.read(bus, self.registers.read16(&Reg16::HL)),
+                    0xD6 | 0xFE => self.fetch_byte(bus),
                     _ => self.registers.read8(&Reg8::from(reg_num))
                 };
 
@@ -739,8 +800,8 @@ impl Cpu {
                 let reg_num = opcode & 0x07;
 
                 let to_and_value = match opcode {
-                    0xA6 => mmu.read8(self.registers.read16(&Reg16::HL)),
-                    0xE6 => self.fetch_byte(mmu),
+                    0xA6 => self.read(bus, self.registers.read16(&Reg16::HL)),
+                    0xE6 => self.fetch_byte(bus),
                     _ => self.registers.read8(&Reg8::from(reg_num))
                 };
 
@@ -764,8 +825,8 @@ impl Cpu {
                 let reg_num = opcode & 0x07;
 
                 let to_xor_value = match opcode {
-                    0xAE => mmu.read8(self.registers.read16(&Reg16::HL)),
-                    0xEE => self.fetch_byte(mmu),
+                    0xAE => self.read(bus, self.registers.read16(&Reg16::HL)),
+                    0xEE => self.fetch_byte(bus),
                     _ => self.registers.read8(&Reg8::from(reg_num))
                 };
 
@@ -789,8 +850,8 @@ impl Cpu {
                 let reg_num = opcode & 0x07;
 
                 let to_or_value = match opcode {
-                    0xB6 => mmu.read8(self.registers.read16(&Reg16::HL)),
-                    0xF6 => self.fetch_byte(mmu),
+                    0xB6 => self.read(bus, self.registers.read16(&Reg16::HL)),
+                    0xF6 => self.fetch_byte(bus),
                     _ => self.registers.read8(&Reg8::from(reg_num))
                 };
 
@@ -808,10 +869,8 @@ impl Cpu {
 
             //PREFIX
             0xCB => {
-                let prefixed_opcode = self.fetch_byte(mmu);
-                let cycles = self.handle_prefixed(prefixed_opcode, mmu);
-
-                cycles
+                let prefixed_opcode = self.fetch_byte(bus);
+                self.handle_prefixed(prefixed_opcode, bus)
             },
             
             //JP
@@ -832,7 +891,7 @@ impl Cpu {
                     _ => unreachable!()
                 };
 
-                let addr = self.fetch_word(mmu);
+                let addr = self.fetch_word(bus);
 
                 if condition {
                     self.program_counter = addr;
@@ -859,9 +918,14 @@ impl Cpu {
 
                     _ => unreachable!()
                 };
+
+                // The conditional variants need a cycle to check the flags
+                if opcode != 0xC9 && opcode != 0xD9 {
+                    self.idle(bus);
+                }
                 
                 if condition {
-                    self.program_counter = self.pop_pc_from_stack(mmu);
+                    self.program_counter = self.pop_from_stack(bus);
 
                     //RETI
                     if opcode == 0xD9 {
@@ -892,10 +956,11 @@ impl Cpu {
                     _ => unreachable!()
                 };
                 
-                let addr = self.fetch_word(mmu);
+                let addr = self.fetch_word(bus);
 
                 if condition {
-                    self.push_pc_to_stack(mmu);
+                    self.idle(bus);
+                    self.push_to_stack(bus, self.program_counter);
                     
                     self.program_counter = addr;
                     
@@ -908,9 +973,7 @@ impl Cpu {
             //POP
             0xC1 | 0xD1 | 0xE1 | 0xF1 => {
                 let reg16 = Reg16::from((opcode >> 4) & 0x03);
-                let val = mmu.read16(self.stack_pointer);
-
-                self.stack_pointer += 2;
+                let val = self.pop_from_stack(bus);
 
                 if reg16 == Reg16::AF {
                     self.registers.write16(&reg16, val & 0xFFF0);
@@ -925,16 +988,17 @@ impl Cpu {
             0xC5 | 0xD5 | 0xE5 | 0xF5 => {
                 let reg16 = Reg16::from((opcode >> 4) & 0x03);
                 let val = self.registers.read16(&reg16);
-                
-                self.stack_pointer -= 2;
-                mmu.write16(self.stack_pointer, val);
+
+                self.idle(bus);
+                self.push_to_stack(bus, val);
             
                 16
             },
 
             //RST vec
             0xC7 | 0xD7 | 0xE7 | 0xF7 | 0xCF | 0xDF | 0xEF | 0xFF => {
-                self.push_pc_to_stack(mmu);
+                self.idle(bus);
+                self.push_to_stack(bus, self.program_counter);
                 
                 let vec = opcode & 0x38;
                 self.program_counter = vec as u16;
@@ -978,31 +1042,25 @@ impl Cpu {
         cycles
     }
 
-    fn push_pc_to_stack(&mut self, mmu: &mut Mmu) {
-        let pc_high = (self.program_counter >> 8) as u8;
-        let pc_low = (self.program_counter as u8) & 0xFF;
-
+    fn push_to_stack(&mut self, bus: &mut impl Bus, value: u16) {
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
-        mmu.write8(self.stack_pointer, pc_high);
+        self.write(bus, self.stack_pointer, (value >> 8) as u8);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
-        mmu.write8(self.stack_pointer, pc_low);
+        self.write(bus, self.stack_pointer, value as u8);
     }
 
-    fn pop_pc_from_stack(&mut self, mmu: &mut Mmu) -> u16 {
-        let byte_low = mmu.read8(self.stack_pointer) as u16;
+    fn pop_from_stack(&mut self, bus: &mut impl Bus) -> u16 {
+        let byte_low = self.read(bus, self.stack_pointer) as u16;
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
 
-        let byte_high = mmu.read8(self.stack_pointer) as u16;
+        let byte_high = self.read(bus, self.stack_pointer) as u16;
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
 
         (byte_high << 8) | byte_low
     }
 
-    pub fn handle_interrupts(&mut self, mmu: &mut Mmu) -> u8 {
-        let ie = mmu.read8(0xFFFF) & 0x1F;
-        let iflag = mmu.read8(0xFF0F) & 0x1F;
-        
-        let pending = ie & iflag;
+    pub fn handle_interrupts(&mut self, bus: &mut impl Bus) -> u8 {
+        let pending = bus.pending_interrupts();
         if pending == 0 || !self.ime { 
             return 0; 
         }
@@ -1015,8 +1073,26 @@ impl Cpu {
         self.ime = false;
         self.halted = false;
 
-        self.push_pc_to_stack(mmu);
+        self.idle(bus);
+        self.idle(bus);
+
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        self.write(bus, self.stack_pointer, (self.program_counter >> 8) as u8);
+
+        // The interrupt to serve is only picked after the high byte of PC
+        // was pushed. If that write cleared its IE bit, a lower priority one
+        // is taken instead, or none at all and execution continues at 0x0000.
+        let pending = bus.pending_interrupts();
 
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        self.write(bus, self.stack_pointer, self.program_counter as u8);
+
+        if pending == 0 {
+            self.program_counter = 0x0000;
+            return 20;
+        }
+
+        let i = pending.trailing_zeros() as u8;
         self.program_counter = match i {
             0 => 0x40, // V-Blank
             1 => 0x48, // LCD STAT
@@ -1026,17 +1102,39 @@ impl Cpu {
             _ => unreachable!(),
         };
 
-        let new_if = (iflag & !(1 << i)) & 0x1F;
-        mmu.write8(0xFF0F, new_if);
-        
+        // Only this request is acknowledged, others that came in during
+        // the push stay pending
+        bus.acknowledge_interrupt(i);
+        self.idle(bus);
+
         20
     }
 
+    /// Reads a byte from the bus, which takes one M-cycle
+    fn read(&mut self, bus: &mut impl Bus, addr: u16) -> u8 {
+        let value = bus.read8(addr);
+        self.idle(bus);
+
+        value
+    }
+
+    /// Writes a byte to the bus, which takes one M-cycle
+    fn write(&mut self, bus: &mut impl Bus, addr: u16, value: u8) {
+        bus.write8(addr, value);
+        self.idle(bus);
+    }
+
+    /// An M-cycle without bus access, the rest of the system keeps running
+    fn idle(&mut self, bus: &mut impl Bus) {
+        bus.tick(4);
+        self.elapsed += 4;
+    }
+
     /// fetches next byte from MMU
     /// 
     /// increments program counter by 1
-    fn fetch_byte(&mut self, mmu: &Mmu) -> u8 {
-        let val = mmu.read8(self.program_counter);
+    fn fetch_byte(&mut self, bus: &mut impl Bus) -> u8 {
+        let val = self.read(bus, self.program_counter);
         if self.halt_bug {
             self.halt_bug = false;
         } else {
@@ -1049,9 +1147,9 @@ impl Cpu {
     /// fetches next word (2 bytes) from MMU
     /// 
     /// increments program counter by 2
-    fn fetch_word(&mut self, mmu: &Mmu) -> u16 {
-        let low_byte = self.fetch_byte(mmu) as u16;
-        let high_byte = self.fetch_byte(mmu) as u16;
+    fn fetch_word(&mut self, bus: &mut impl Bus) -> u16 {
+        let low_byte = self.fetch_byte(bus) as u16;
+        let high_byte = self.fetch_byte(bus) as u16;
 
         (high_byte << 8) + low_byte
     }
@@ -1064,7 +1162,7 @@ impl Cpu {
         flags.set_flag(Flags::CARRY, pushed_out == 1); 
     }
 
-    fn handle_prefixed(&mut self, prefixed_opcode: u8, mmu: &mut Mmu) -> u8 {
+    fn handle_prefixed(&mut self, prefixed_opcode: u8, bus: &mut impl Bus) -> u8 {
 
         let destination_num = prefixed_opcode & 0x07;
         let destination = match destination_num {
@@ -1073,7 +1171,7 @@ impl Cpu {
         };
 
         let dst_value = match destination {
-            Operand8::IndirectHL => mmu.read8(self.registers.read16(&Reg16::HL)),
+            Operand8::IndirectHL => self.read(bus, self.registers.read16(&Reg16::HL)),
             Operand8::Register(ref reg) => self.registers.read8(reg),
         };
 
@@ -1176,12 +1274,10 @@ impl Cpu {
                 let bit_mask: u8 = 1 << test_bit;
 
                 //if bit 6 is 0: RES else SET
-                let result = match (prefixed_opcode >> 0x06) & 1 {
+                match (prefixed_opcode >> 0x06) & 1 {
                     0 => dst_value & !bit_mask,
                     _ => dst_value | bit_mask
-                };
-
-                result
+                }
             }
         };
 
@@ -1191,11 +1287,74 @@ impl Cpu {
         }
         
         match destination {
-            Operand8::IndirectHL => mmu.write8(self.registers.read16(&Reg16::HL), result),
+            Operand8::IndirectHL => self.write(bus, self.registers.read16(&Reg16::HL), result),
             Operand8::Register(ref reg) => self.registers.write8(reg, result),
         }
         
         if destination_num == 6 { 16 } else { 8 }
         
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::bus::FlatBus;
+
+    /// Plain RAM with the next event a fixed number of cycles away
+    struct EventBus {
+        bus: FlatBus,
+        until_event: Option<u64>,
+    }
+
+    impl Bus for EventBus {
+        fn read8(&self, addr: u16) -> u8 {
+            self.bus.read8(addr)
+        }
+
+        fn write8(&mut self, addr: u16, value: u8) {
+            self.bus.write8(addr, value);
+        }
+
+        fn tick(&mut self, cycles: u8) {
+            self.bus.tick(cycles);
+        }
+
+        fn cycles_until_event(&self) -> Option<u64> {
+            self.until_event
+        }
+    }
+
+    fn halted_step(until_event: Option<u64>) -> u8 {
+        let mut cpu = Cpu::new();
+        cpu.halted = true;
+
+        cpu.step(&mut EventBus { bus: FlatBus::new(), until_event })
+    }
+
+    #[test]
+    fn halt_skips_to_the_next_event() {
+        assert_eq!(halted_step(Some(40)), 40);
+        // In whole M-cycles, at least one
+        assert_eq!(halted_step(Some(37)), 40);
+        assert_eq!(halted_step(Some(0)), 4);
+    }
+
+    #[test]
+    fn halt_skip_is_capped() {
+        assert_eq!(halted_step(Some(100_000)), MAX_HALT_SKIP);
+        assert_eq!(halted_step(Some(MAX_HALT_SKIP as u64 + 1)), MAX_HALT_SKIP);
+        assert_eq!(halted_step(None), MAX_HALT_SKIP);
+    }
+
+    #[test]
+    fn pending_interrupt_ends_halt() {
+        let mut cpu = Cpu::new();
+        cpu.halted = true;
+        let mut bus = EventBus { bus: FlatBus::new(), until_event: None };
+        bus.bus.memory[0xFFFF] = 0x04;
+        bus.bus.memory[0xFF0F] = 0x04;
+
+        assert_eq!(cpu.step(&mut bus), 4);
+        assert!(!cpu.halted);
+    }
+}
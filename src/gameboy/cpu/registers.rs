@@ -70,6 +70,20 @@ impl Registers {
         }
     }
 
+    /// All registers cleared, as found before the boot ROM ran
+    pub fn power_on() -> Self {
+        Registers {
+            a: 0x00,
+            flag_register: FlagsRegister { flags: 0x00 },
+            b: 0x00,
+            c: 0x00,
+            d: 0x00,
+            e: 0x00,
+            h: 0x00,
+            l: 0x00,
+        }
+    }
+
     pub fn read8(&self, reg: &Reg8) -> u8 {
         match reg {
             Reg8::A  => self.a,
@@ -0,0 +1,240 @@
+use std::fmt;
+
+use super::mmu::Mmu;
+
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "[HL]", "A"];
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const R16_MEMORY: [&str; 4] = ["[BC]", "[DE]", "[HL+]", "[HL-]"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBC", "AND", "XOR", "OR", "CP"];
+const ROTATIONS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+/// A single decoded SM83 instruction
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    /// Address of the opcode
+    pub addr: u16,
+    /// Opcode (with the 0xCB prefix) and operands, 1 to 3 bytes
+    pub bytes: Vec<u8>,
+    /// Mnemonic and operands in RGBDS syntax, e.g. `JR NZ, $0150`
+    pub text: String,
+}
+
+impl Instruction {
+    /// Instruction length in bytes
+    pub fn length(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    /// Address of the instruction following this one
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.length())
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ");
+
+        write!(f, "{:04X}: {:<8}  {}", self.addr, bytes, self.text)
+    }
+}
+
+/// Decodes the instruction at `addr`, reading its bytes through `fetch`
+pub fn decode(addr: u16, fetch: impl Fn(u16) -> u8) -> Instruction {
+    let opcode = fetch(addr);
+    let n8 = fetch(addr.wrapping_add(1));
+    let n16 = u16::from_le_bytes([n8, fetch(addr.wrapping_add(2))]);
+    // Relative jumps are taken from the address after the operand
+    let jr_target = addr.wrapping_add(2).wrapping_add(n8 as i8 as u16);
+
+    let y = ((opcode >> 3) & 0x07) as usize;
+    let z = (opcode & 0x07) as usize;
+    let p = y >> 1;
+
+    let (text, length) = match opcode {
+        0x00 => ("NOP".to_string(), 1),
+        0x01 | 0x11 | 0x21 | 0x31 => (format!("LD {}, ${:04X}", R16[p], n16), 3),
+        0x02 | 0x12 | 0x22 | 0x32 => (format!("LD {}, A", R16_MEMORY[p]), 1),
+        0x03 | 0x13 | 0x23 | 0x33 => (format!("INC {}", R16[p]), 1),
+        0x0B | 0x1B | 0x2B | 0x3B => (format!("DEC {}", R16[p]), 1),
+        0x09 | 0x19 | 0x29 | 0x39 => (format!("ADD HL, {}", R16[p]), 1),
+        0x0A | 0x1A | 0x2A | 0x3A => (format!("LD A, {}", R16_MEMORY[p]), 1),
+        0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => (format!("INC {}", R8[y]), 1),
+        0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => (format!("DEC {}", R8[y]), 1),
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => (format!("LD {}, ${:02X}", R8[y], n8), 2),
+        0x07 => ("RLCA".to_string(), 1),
+        0x0F => ("RRCA".to_string(), 1),
+        0x17 => ("RLA".to_string(), 1),
+        0x1F => ("RRA".to_string(), 1),
+        0x27 => ("DAA".to_string(), 1),
+        0x2F => ("CPL".to_string(), 1),
+        0x37 => ("SCF".to_string(), 1),
+        0x3F => ("CCF".to_string(), 1),
+        0x08 => (format!("LD [${:04X}], SP", n16), 3),
+        // STOP skips the byte after it
+        0x10 => ("STOP".to_string(), 2),
+        0x18 => (format!("JR ${:04X}", jr_target), 2),
+        0x20 | 0x28 | 0x30 | 0x38 => (format!("JR {}, ${:04X}", CONDITIONS[y - 4], jr_target), 2),
+
+        0x76 => ("HALT".to_string(), 1),
+        0x40..=0x7F => (format!("LD {}, {}", R8[y], R8[z]), 1),
+        0x80..=0xBF => (format!("{} A, {}", ALU[y], R8[z]), 1),
+
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => (format!("RET {}", CONDITIONS[y]), 1),
+        0xC9 => ("RET".to_string(), 1),
+        0xD9 => ("RETI".to_string(), 1),
+        0xC1 | 0xD1 | 0xE1 | 0xF1 => (format!("POP {}", R16_STACK[p]), 1),
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => (format!("PUSH {}", R16_STACK[p]), 1),
+        0xC2 | 0xCA | 0xD2 | 0xDA => (format!("JP {}, ${:04X}", CONDITIONS[y], n16), 3),
+        0xC3 => (format!("JP ${:04X}", n16), 3),
+        0xE9 => ("JP HL".to_string(), 1),
+        0xC4 | 0xCC | 0xD4 | 0xDC => (format!("CALL {}, ${:04X}", CONDITIONS[y], n16), 3),
+        0xCD => (format!("CALL ${:04X}", n16), 3),
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => (format!("{} A, ${:02X}", ALU[y], n8), 2),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => (format!("RST ${:02X}", y * 8), 1),
+        0xCB => (decode_prefixed(n8), 2),
+
+        0xE0 => (format!("LDH [${:04X}], A", 0xFF00 | n8 as u16), 2),
+        0xF0 => (format!("LDH A, [${:04X}]", 0xFF00 | n8 as u16), 2),
+        0xE2 => ("LDH [C], A".to_string(), 1),
+        0xF2 => ("LDH A, [C]".to_string(), 1),
+        0xEA => (format!("LD [${:04X}], A", n16), 3),
+        0xFA => (format!("LD A, [${:04X}]", n16), 3),
+        0xE8 => (format!("ADD SP, {}", signed(n8)), 2),
+        0xF8 => (format!("LD HL, SP{}", signed(n8)), 2),
+        0xF9 => ("LD SP, HL".to_string(), 1),
+        0xF3 => ("DI".to_string(), 1),
+        0xFB => ("EI".to_string(), 1),
+
+        // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD
+        // don't exist, so they can only be data
+        _ => (format!("DB ${:02X}", opcode), 1),
+    };
+
+    Instruction {
+        addr,
+        bytes: (0..length).map(|i| fetch(addr.wrapping_add(i))).collect(),
+        text,
+    }
+}
+
+fn decode_prefixed(opcode: u8) -> String {
+    let bit = (opcode >> 3) & 0x07;
+    let register = R8[(opcode & 0x07) as usize];
+
+    match opcode >> 6 {
+        0 => format!("{} {}", ROTATIONS[bit as usize], register),
+        1 => format!("BIT {}, {}", bit, register),
+        2 => format!("RES {}, {}", bit, register),
+        _ => format!("SET {}, {}", bit, register),
+    }
+}
+
+/// Signed 8 bit offset as `+$05` or `-$03`
+fn signed(value: u8) -> String {
+    let value = value as i8;
+    let sign = if value < 0 { '-' } else { '+' };
+
+    format!("{}${:02X}", sign, value.unsigned_abs())
+}
+
+/// Disassembles a whole byte slice that is mapped at `addr`. Trailing
+/// bytes too short for their instruction are listed as `DB`.
+pub fn disassemble(bytes: &[u8], addr: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let current = addr.wrapping_add(offset as u16);
+        let mut instruction = decode(current, |at| {
+            bytes.get(offset + at.wrapping_sub(current) as usize).copied().unwrap_or(0x00)
+        });
+
+        if offset + instruction.bytes.len() > bytes.len() {
+            instruction = Instruction {
+                addr: current,
+                bytes: vec![bytes[offset]],
+                text: format!("DB ${:02X}", bytes[offset]),
+            };
+        }
+
+        offset += instruction.bytes.len();
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+/// Disassembles `count` instructions from the live memory map. With a
+/// `bank` the ROM area reads from that bank instead of the mapped one.
+pub fn disassemble_mmu(mmu: &Mmu, bank: Option<usize>, addr: u16, count: usize) -> Vec<Instruction> {
+    let fetch = |at: u16| match bank {
+        Some(bank) => mmu.read_rom_bank(bank, at),
+        None => mmu.peek8(at),
+    };
+
+    let mut instructions = Vec::with_capacity(count);
+    let mut current = addr;
+
+    for _ in 0..count {
+        let instruction = decode(current, fetch);
+        current = instruction.next_addr();
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8], addr: u16) -> Vec<String> {
+        disassemble(bytes, addr).into_iter().map(|instruction| instruction.text).collect()
+    }
+
+    #[test]
+    fn cb_prefix() {
+        assert_eq!(text(&[0xCB, 0x37, 0xCB, 0x7E, 0xCB, 0x87, 0xCB, 0xFF], 0), [
+            "SWAP A", "BIT 7, [HL]", "RES 0, A", "SET 7, A",
+        ]);
+    }
+
+    #[test]
+    fn immediates_are_little_endian() {
+        assert_eq!(text(&[0x01, 0x34, 0x12, 0xEA, 0x00, 0xC0, 0xCD, 0x50, 0x01], 0), [
+            "LD BC, $1234", "LD [$C000], A", "CALL $0150",
+        ]);
+        assert_eq!(text(&[0x3E, 0x42, 0xE0, 0x40, 0xF8, 0xFE], 0), [
+            "LD A, $42", "LDH [$FF40], A", "LD HL, SP-$02",
+        ]);
+    }
+
+    #[test]
+    fn relative_jumps_target_the_next_instruction_plus_offset() {
+        let instructions = disassemble(&[0x18, 0xFE, 0x20, 0x05, 0x38, 0x80], 0x0150);
+
+        assert_eq!(instructions[0].text, "JR $0150");
+        assert_eq!(instructions[1].text, "JR NZ, $0159");
+        assert_eq!(instructions[2].text, "JR C, $00D6");
+        assert_eq!(instructions[2].next_addr(), 0x0156);
+    }
+
+    #[test]
+    fn invalid_opcodes_are_data() {
+        let invalid = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+
+        for opcode in invalid {
+            let instruction = decode(0, |_| opcode);
+            assert_eq!(instruction.text, format!("DB ${:02X}", opcode));
+            assert_eq!(instruction.length(), 1);
+        }
+    }
+
+    #[test]
+    fn truncated_instruction_is_data() {
+        assert_eq!(text(&[0x00, 0xC3, 0x50], 0), ["NOP", "DB $C3", "LD D, B"]);
+    }
+}
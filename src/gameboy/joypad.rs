@@ -37,6 +37,12 @@ pub struct Joypad {
     directions: u8, // Bit 0-3
 }
 
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Joypad {
     pub fn new() -> Self {
         Self {
@@ -1,250 +1,340 @@
-use super::{cpu::Interrupt, super::gameboy::{apu::Apu, timer::{Timer, TimerAddr}, joypad::{Joypad, Key}}};
-
+pub mod io;
+
+use super::{cartridge::Cartridge, cpu::Interrupt, super::gameboy::{apu::Apu, timer::Timer, joypad::{Joypad, Key}}};
+use super::{ppu::{Ppu, Renderer}, screen::framebuffer::Framebuffer};
+use super::model::Model;
+use super::scheduler::{EventKind, Scheduler};
+use crate::rom::CartridgeHeader;
+
+/// T-cycles per serial bit on the internal 8192 Hz clock
+const SERIAL_BIT_CYCLES: u64 = 512;
+/// T-cycles before an OAM DMA copies its first byte
+const DMA_STARTUP_CYCLES: u64 = 4;
+/// T-cycles an OAM DMA needs per byte
+const DMA_BYTE_CYCLES: u64 = 4;
+
+/// A running OAM DMA transfer
+struct Dma {
+    source: u16,
+    started_at: u64,
+    /// Bytes already copied to OAM
+    copied: u16,
+}
 
 /// struct that represent the Memory Managment Unit (MMU)
 pub struct Mmu {
-    rom: Vec<u8>,
-    vram: [u8; 0x2000],
-    eram: Vec<u8>,
+    cartridge: Cartridge,
+    /// Mapped over 0x0000-0x00FF until the game writes to 0xFF50
+    boot_rom: Option<Vec<u8>>,
+    eram_dirty: bool,
     wram: [u8; 0x2000],
-    oam:  [u8; 0xA0],
     hram: [u8; 0x7F],
     io:   [u8; 0x80],
     ie:   u8,
 
     joypad: Joypad,
-    
-    cartridge_type: u8,
-
-    rom_bank: u8, 
-    ram_bank: u8,
-    ram_enabled: bool,
-
-    banking_mode: u8,
 
     apu: Apu,
     timer: Timer,
-
-    mbc3_rtc_sel: Option<u8>,
+    ppu: Ppu,
+    frame_ready: bool,
+
+    /// Timer, APU, PPU and cartridge are only run when one of their events
+    /// is due or one of their registers is accessed
+    scheduler: Scheduler,
+    apu_synced_at: u64,
+    ppu_synced_at: u64,
+    cartridge_synced_at: u64,
+
+    /// Bits left in the running serial transfer
+    serial_bits: u8,
+    /// Every byte the game started to send, test ROMs print their results this way
+    serial_output: Vec<u8>,
+    dma: Option<Dma>,
+
+    /// LY always reads 0x90, as Gameboy Doctor traces expect
+    fixed_ly: bool,
+    /// VRAM and OAM are locked while the PPU uses them
+    restrict_access: bool,
 }
 
 impl Mmu {
-    pub fn new(rom: Vec<u8>, apu: Apu, timer: Timer) -> Self {
-        let cartridge_type = rom.get(0x0147).copied().unwrap_or(0);
-        let ram_size = Self::ram_size_from_header(
-            rom.get(0x0149).copied().unwrap_or(0)
-        );
-        
-        Self {
-            rom,
-            vram: [0; 0x2000],
-            eram: vec![0; ram_size],
+    pub fn new(cartridge: Cartridge, apu: Apu, timer: Timer, ppu: Ppu) -> Self {
+        let mut mmu = Self {
+            cartridge,
+            boot_rom: None,
+            eram_dirty: false,
             wram: [0; 0x2000],
-            oam: [0; 0xA0],
             hram: [0; 0x7F],
             io: [0xFF; 0x80],
             ie: 0,  
 
             joypad: Joypad::new(),
 
-            cartridge_type,
-            
-            rom_bank: 1,
-            ram_bank: 0,
-            ram_enabled: false,
-
-            banking_mode: 0,
-
             apu,
             timer,
+            ppu,
+            frame_ready: false,
+
+            scheduler: Scheduler::new(),
+            apu_synced_at: 0,
+            ppu_synced_at: 0,
+            cartridge_synced_at: 0,
+
+            serial_bits: 0,
+            serial_output: Vec::new(),
+            dma: None,
 
-            mbc3_rtc_sel: None,
+            fixed_ly: false,
+            restrict_access: true,
+        };
+
+        mmu.schedule_timer_events(0);
+        mmu.sync_ppu(0);
+        mmu
+    }
+
+    /// Puts the I/O registers, DIV and the APU into the state the boot ROM
+    /// of `model` leaves behind when jumping to 0x0100
+    pub fn post_boot(&mut self, model: Model) {
+        self.timer = Timer::with_counter(model.boot_div_counter());
+        self.schedule_timer_events(self.scheduler.now());
+
+        for (addr, value) in model.boot_io_registers() {
+            self.write8(addr, value);
         }
+
+        if let Some(wave_ram) = model.boot_wave_ram() {
+            for (i, &value) in wave_ram.iter().enumerate() {
+                self.write8(0xFF30 + i as u16, value);
+            }
+        }
+
+        if model.plays_boot_chime() {
+            self.apu.ch1.finish_boot_chime();
+        }
+
+        self.io[0x46] = model.boot_dma_register();
     }
 
-    pub fn read8(&self, addr: u16) -> u8 {      
-        match addr {
-            0x0000..=0x3FFF => self.rom.get(addr as usize).copied().unwrap_or(0xFF), 
-            0x4000..=0x7FFF => {
-                let bank_addr = (self.rom_bank as usize) * 0x4000 + ((addr - 0x4000) as usize);
-                self.rom.get(bank_addr).copied().unwrap_or(0xFF)
-            },
-            0x8000..=0x9FFF => self.vram[(addr - 0x8000) as usize],
-            0xA000..=0xBFFF => {
-                if self.mbc3_rtc_sel.is_some() { return 0xFF; }
+    /// Maps a boot ROM over 0x0000-0x00FF and puts the I/O registers into
+    /// their power on state, so the boot ROM can set them up itself
+    pub fn map_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        self.boot_rom = Some(boot_rom);
 
-                if !self.ram_enabled || self.eram.is_empty() { return 0xFF; }
+        self.io = [0; 0x80];
+        self.apu.write_nr52(0);
 
-                let bank = self.ram_bank as usize;
-                let offset = bank * 0x2000 + (addr - 0xA000) as usize;
-                
-                self.eram.get(offset).copied().unwrap_or(0xFF)
-            },
+        let now = self.scheduler.now();
+        self.sync_ppu(now);
+        self.ppu.power_on();
+        self.sync_ppu(now);
+    }
+
+    pub fn read8(&self, addr: u16) -> u8 {      
+        if let Some(boot_rom) = &self.boot_rom && addr < 0x0100 {
+            return boot_rom[addr as usize];
+        }
+
+        match addr {
+            0x0000..=0x7FFF => self.cartridge.read_rom(addr),
+            0x8000..=0x9FFF if !self.vram_accessible() => 0xFF,
+            0x8000..=0x9FFF => self.ppu.read_vram(addr),
+            0xA000..=0xBFFF => self.cartridge.read_ram(addr),
             0xC000..=0xDFFF => self.wram[(addr - 0xC000) as usize],
             0xE000..=0xFDFF => self.wram[(addr - 0xE000) as usize % 0x2000],
-            0xFE00..=0xFE9F => self.oam[(addr - 0xFE00) as usize],
+            // The DMA has the bus to OAM for itself
+            0xFE00..=0xFE9F if self.dma.is_some() || !self.oam_accessible() => 0xFF,
+            0xFE00..=0xFE9F => self.ppu.read_oam(addr),
+            // The unusable area after OAM reads as 0 on a DMG
+            0xFEA0..=0xFEFF => 0x00,
             0xFF00          => self.joypad.read(),
-            0xFF0F          => self.io[0x0F] | 0xE0,
-            //APU Registers
-            0xFF10 => self.io[0x10],
-            0xFF11 => self.io[0x11] | 0x3F,
-            0xFF12 => self.io[0x12],
-            0xFF14 => self.io[0x14] | 0xBF,
-            
-            0xFF16 => self.io[0x16] | 0x3F,
-            0xFF17 => self.io[0x17],
-            0xFF19 => self.io[0x19] | 0xBF,
-            
-            0xFF1A => self.io[0x1A] | 0x7F,
-            0xFF1C => self.io[0x1C] | 0x9F,
-            0xFF1E => self.io[0x1E] | 0xBF,
-            
-            0xFF26 => self.apu.read_nr52() | 0x70,
+            0xFF04..=0xFF07 => self.timer.read(addr, self.scheduler.now()) | io::READ_MASKS[(addr - 0xFF00) as usize],
+            0xFF26          => self.apu.read_nr52() | io::READ_MASKS[0x26],
             0xFF30..=0xFF3F => self.apu.ch3.read_wave_ram(addr - 0xFF30),
-            //
+            0xFF44 if self.fixed_ly => 0x90,
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.read_register(addr) | io::READ_MASKS[(addr - 0xFF00) as usize],
+            0xFF01..=0xFF7F => self.io[(addr - 0xFF00) as usize] | io::READ_MASKS[(addr - 0xFF00) as usize],
             0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize],
-            0xFF00..=0xFF7F => self.io[(addr - 0xFF00) as usize],
             0xFFFF          => self.ie,
-            _               => 0xFF,
         }
     }
 
-    pub fn write8(&mut self, addr: u16, value: u8) {
-        match addr {
-            0x0000..=0x1FFF => self.ram_enabled = (value & 0x0F) == 0x0A,
-            0x2000..=0x3FFF => {
-                if self.cartridge_type == 0x00 { return; }
-
-                if self.is_mbc3() {
-                    let mut bank = value & 0x7F;
-                    if bank == 0 { bank = 1; }
-                    self.rom_bank = bank;
-                    return;
-                }
+    /// The ROM bank `addr` reads from, None outside of the cartridge ROM
+    pub fn rom_bank(&self, addr: u16) -> Option<usize> {
+        if self.boot_rom.is_some() && addr < 0x0100 {
+            return None;
+        }
 
-                if self.is_mbc1() {
-                    let mut bank = value & 0x1F;
-                    if bank == 0 { bank = 1; }
-                    self.rom_bank = (self.rom_bank & 0xE0) | bank;
-                }
-            },
-            0x4000..=0x5FFF => {
-                if self.cartridge_type == 0x00 { return; }
-
-                if self.is_mbc3() {
-                    if (0x08..=0x0C).contains(&value) {
-                        self.mbc3_rtc_sel = Some(value);
-                    } else {
-                        self.mbc3_rtc_sel = None;
-                        self.ram_bank = value & 0x03;
-                    }
-                    return;
-                }
+        (addr < 0x8000).then(|| self.cartridge.rom_bank(addr))
+    }
 
-                if self.is_mbc1() {
-                    let bank_bits = value & 0x03;
-                    
-                    if self.banking_mode == 0 {
-                        self.rom_bank = (self.rom_bank & 0x1F) | (bank_bits << 5);
-                    } else {
-                        self.ram_bank = bank_bits;
-                    }
-                }
-            },
-            0x6000..=0x7FFF => {
-                if self.cartridge_type == 0x00 { return; }
+    /// Makes LY read 0x90 no matter what the PPU does
+    pub fn set_fixed_ly(&mut self, enabled: bool) {
+        self.fixed_ly = enabled;
+    }
 
-                if self.is_mbc1() {
-                    self.banking_mode = value & 0x01;
-                }
-            },
-            0x8000..=0x9FFF => self.vram[(addr - 0x8000) as usize] = value,
-            0xA000..=0xBFFF => {
-                if self.mbc3_rtc_sel.is_some() { return; }
+    /// Lets the CPU reach VRAM and OAM in every PPU mode (false), for debugging
+    pub fn set_restrict_access(&mut self, enabled: bool) {
+        self.restrict_access = enabled;
+    }
 
-                if !self.ram_enabled || self.eram.is_empty() { return; }
+    fn vram_accessible(&self) -> bool {
+        !self.restrict_access || self.ppu.vram_accessible()
+    }
 
-                let bank = self.ram_bank as usize;
-                let offset = bank * 0x2000 + (addr - 0xA000) as usize;
+    fn oam_accessible(&self) -> bool {
+        !self.restrict_access || self.ppu.oam_accessible()
+    }
 
-                if let Some(byte) = self.eram.get_mut(offset) {
-                    *byte = value;
-                }
+    /// Like `read8`, but VRAM and OAM read what they hold in every PPU
+    /// mode and during DMA, for debug output
+    pub fn peek8(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => self.ppu.read_vram(addr),
+            0xFE00..=0xFE9F => self.ppu.read_oam(addr),
+            _ => self.read8(addr),
+        }
+    }
+
+    /// Like `peek8`, but the ROM area reads from `bank` instead of the
+    /// mapped bank
+    pub fn read_rom_bank(&self, bank: usize, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.cartridge.read_rom_bank(bank, addr),
+            _ => self.peek8(addr),
+        }
+    }
+
+    pub fn write8(&mut self, addr: u16, value: u8) {
+        let now = self.scheduler.now();
+
+        // The channels have to run up to the write with the old settings
+        if (0xFF10..=0xFF3F).contains(&addr) {
+            self.sync_apu(now);
+        }
+
+        // A dot by dot renderer has to draw up to the write with the old values
+        let ppu_write = matches!(addr, 0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF41..=0xFF4B);
+        if ppu_write && self.ppu.renders_per_dot() {
+            self.sync_ppu(now);
+        }
+
+        match addr {
+            0x0000..=0x7FFF => {
+                self.sync_cartridge(now);
+                self.cartridge.write_register(addr, value);
+            },
+            0x8000..=0x9FFF if !self.vram_accessible() => {},
+            0x8000..=0x9FFF => self.ppu.write_vram(addr, value),
+            0xA000..=0xBFFF => {
+                self.sync_cartridge(now);
+                self.eram_dirty |= self.cartridge.write_ram(addr, value);
             },
             0xC000..=0xDFFF => self.wram[(addr - 0xC000) as usize] = value,
             0xE000..=0xFDFF => self.wram[(addr - 0xE000) as usize] = value,
-            0xFE00..=0xFE9F => self.oam[(addr - 0xFE00) as usize] = value,
+            0xFE00..=0xFE9F if self.dma.is_some() || !self.oam_accessible() => {},
+            0xFE00..=0xFE9F => self.ppu.write_oam(addr, value),
             0xFF00          => self.joypad.write(value),
             0xFF02          => {
-                self.io[0x02] = value;
-            
-                if value & 0x80 != 0 {
-                    self.io[0x01] = 0xFF;
-                    self.io[0x02] = value & 0x7F; 
-                    self.request_interrupt(Interrupt::Serial);
+                self.write_io(0xFF02, value);
+
+                // Without a link partner only a transfer on the internal
+                // clock ever finishes
+                if value & 0x81 == 0x81 {
+                    self.serial_output.push(self.io[0x01]);
+                    self.serial_bits = 8;
+                    self.scheduler.schedule(EventKind::SerialBit, now + SERIAL_BIT_CYCLES);
+                } else {
+                    self.scheduler.cancel(EventKind::SerialBit);
                 }
             },
-            0xFF04          => {
-                self.timer.reset_divider();
-                self.io[0x04] = 0
-            }, 
+            0xFF04..=0xFF07 => {
+                let update = self.timer.write(addr, value, now);
+
+                if update.overflow {
+                    self.scheduler.schedule(EventKind::TimerReload, now + 4);
+                }
+                if !self.timer.reload_pending() {
+                    self.scheduler.cancel(EventKind::TimerReload);
+                }
+                if update.frame_sequencer_clock {
+                    self.apu.step_frame_sequencer();
+                }
+
+                self.schedule_timer_events(now);
+            },
 
             // APU Registers
+            // While the APU is off only the length counters can be written
+            0xFF11 if !self.apu.enabled => self.apu.ch1.length_counter = 64 - (value & 0x3F),
+            0xFF16 if !self.apu.enabled => self.apu.ch2.write_length(value),
+            addr if io::APU_REGISTERS.contains(&addr) && !matches!(addr, 0xFF1B | 0xFF20) && !self.apu.enabled => {},
             0xFF10 => {
-                self.io[0x10] = value | 0x80;
+                self.write_io(0xFF10, value);
                 self.apu.ch1.write_sweep(value);
             },
             0xFF11 => {
-                self.io[0x11] = value;
+                self.write_io(0xFF11, value);
                 self.apu.ch1.duty = value >> 6;
                 self.apu.ch1.length_counter = 64 - (value & 0x3F);
             }
             0xFF12 => {
-                self.io[0x12] = value;
+                self.write_io(0xFF12, value);
                 self.apu.ch1.write_envelope(value);
             }
             0xFF13 => self.apu.ch1.frequency = (self.apu.ch1.frequency & 0x0700) | value as u16,
             0xFF14 => {
-                self.io[0x14] = value;
+                self.write_io(0xFF14, value);
                 self.apu.ch1.write_freq_high(value);
             }
             
             // Channel 2
             0xFF16 => {
-                self.io[0x16] = value;
+                self.write_io(0xFF16, value);
                 self.apu.ch2.write_duty_length(value);
             }
             0xFF17 => {
-                self.io[0x17] = value;
+                self.write_io(0xFF17, value);
                 self.apu.ch2.write_envelope(value);
             }
             0xFF18 => self.apu.ch2.write_freq_low(value),
             0xFF19 => {
-                self.io[0x19] = value;
+                self.write_io(0xFF19, value);
                 self.apu.ch2.write_freq_high(value);
             }
             
             // Channel 3 (Wave)
             0xFF1A => {
-                self.io[0x1A] = value;
+                self.write_io(0xFF1A, value);
                 self.apu.ch3.write_nr30(value);
             }
             0xFF1B => self.apu.ch3.write_nr31(value as u16),
             0xFF1C => {
-                self.io[0x1C] = value;
+                self.write_io(0xFF1C, value);
                 self.apu.ch3.write_nr32(value);
             }
             0xFF1D => self.apu.ch3.write_freq_low(value),
             0xFF1E => {
-                self.io[0x1E] = value;
+                self.write_io(0xFF1E, value);
                 self.apu.ch3.write_freq_high(value);
             }
             
             // Channel 4 (Noise)
-            0xFF20 => self.apu.ch4.write_nr41(value),
-            0xFF21 => self.apu.ch4.write_nr42(value),
-            0xFF22 => self.apu.ch4.write_nr43(value),
+            0xFF20 => {
+                self.write_io(0xFF20, value);
+                self.apu.ch4.write_nr41(value);
+            }
+            0xFF21 => {
+                self.write_io(0xFF21, value);
+                self.apu.ch4.write_nr42(value);
+            }
+            0xFF22 => {
+                self.write_io(0xFF22, value);
+                self.apu.ch4.write_nr43(value);
+            }
             0xFF23 => {
+                self.write_io(0xFF23, value);
                 self.apu.ch4.write_nr44(value);
                 if value & 0x80 != 0 {
                     self.apu.ch4.trigger();
@@ -252,75 +342,215 @@ impl Mmu {
             }
             
             // Control registers
-            0xFF24 => self.apu.write_nr50(value),
-            0xFF25 => self.apu.write_nr51(value),
-            0xFF26 => self.apu.write_nr52(value),
+            0xFF24 => {
+                self.write_io(0xFF24, value);
+                self.apu.write_nr50(value);
+            }
+            0xFF25 => {
+                self.write_io(0xFF25, value);
+                self.apu.write_nr51(value);
+            }
+            0xFF26 => {
+                // Switching the APU off clears all of its registers
+                if value & 0x80 == 0 {
+                    self.io[0x10..=0x25].fill(0x00);
+                }
+                self.apu.write_nr52(value);
+            }
             
             0xFF30..=0xFF3F => self.apu.ch3.write_wave_ram(addr - 0xFF30, value),
             //
 
-            0xFF0F          => self.io[0x0F] = (self.io[0x0F] & 0xE0) | (value & 0x1F),
-            0xFF41          => {
-                let read_only = self.io[0x41] & 0b0000_0111;
-                let writeable = value & 0b0111_1000;
-                self.io[0x41] = read_only | writeable | 0x80;
-            },         
-            0xFF44          => {},
+            0xFF0F          => self.write_io(addr, value),
+            // Switching the LCD on or off changes when the next mode ends
+            0xFF40          => {
+                self.sync_ppu(now);
+                self.ppu.write_register(addr, value);
+                self.sync_ppu(now);
+            },
+            0xFF41..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.write_register(addr, value),
             0xFF46          => {
-                self.io[0x46] = value;
-
-                let source = (value as u16) << 8;        
-                for i in 0..0xA0 {
-                    let byte = self.read8(source + i);
-                    self.oam[i as usize] = byte;
-                }
+                self.write_io(0xFF46, value);
+
+                self.sync_dma(now);
+                self.dma = Some(Dma {
+                    source: (value as u16) << 8,
+                    started_at: now,
+                    copied: 0,
+                });
+                self.scheduler.schedule(EventKind::DmaDone, now + DMA_STARTUP_CYCLES + 0xA0 * DMA_BYTE_CYCLES);
             },
-            0xFF47..=0xFF49 => self.io[(addr - 0xFF00) as usize] = value,
+            // The boot ROM unmaps itself as its last instruction. There is
+            // no way to map it back in.
+            0xFF50 if value & 0x01 != 0 => self.boot_rom = None,
             0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize] = value,
-            0xFF00..=0xFF7F => self.io[(addr - 0xFF00) as usize] = value,
-            0xFFFF          => self.ie = value & 0x1F,
+            0xFF01..=0xFF7F => self.write_io(addr, value),
+            // All 8 bits are kept, even though only the lower 5 request interrupts
+            0xFFFF          => self.ie = value,
             _               => {},
         }
     }
 
-    pub fn read16(&self, addr: u16) -> u16 {
-        let lower_byte = self.read8(addr) as u16;
-        let higher_byte = self.read8(addr + 1) as u16;
+    /// Stores a write to an I/O register, only changing its writable bits
+    fn write_io(&mut self, addr: u16, value: u8) {
+        let index = (addr - 0xFF00) as usize;
+        let mask = io::WRITE_MASKS[index];
 
-        (higher_byte << 8) | lower_byte
+        self.io[index] = (self.io[index] & !mask) | (value & mask);
     }
 
-    pub fn write16(&mut self, addr: u16, value: u16) {
-        let lower_byte = value & 0x00FF;
-        let higher_byte = value >> 8;
+    /// Advances everything besides the CPU by `cycles`. Only the events
+    /// that became due in that time do any work.
+    pub fn tick(&mut self, cycles: u8) {
+        self.scheduler.advance(cycles as u64);
 
-        self.write8(addr, lower_byte as u8);
-        self.write8(addr + 1, higher_byte as u8);
+        while let Some((event, time)) = self.scheduler.pop_due() {
+            self.handle_event(event, time);
+        }
     }
 
-    pub fn tick(&mut self, cycles: u8) {
-        let tac = self.read8(TimerAddr::TAC as u16);
-        let tima = self.read8(TimerAddr::TIMA as u16);
-        let tma = self.read8(TimerAddr::TMA as u16);
-        
-        let update = self.timer.update(cycles, tac, tima, tma);
-        
-        if let Some(div) = update.new_div {
-            self.write_div(div);
+    /// Cycles until the next scheduled event. Nothing observable happens
+    /// before it unless the CPU accesses a register.
+    pub fn cycles_until_event(&self) -> Option<u64> {
+        self.scheduler.cycles_until_next()
+    }
+
+    fn handle_event(&mut self, event: EventKind, time: u64) {
+        match event {
+            EventKind::PpuMode => {
+                // The PPU has to see what the DMA copied so far
+                self.sync_dma(time);
+                self.sync_ppu(time);
+            },
+            EventKind::TimerOverflow => {
+                if self.timer.sync(time) {
+                    self.scheduler.schedule(EventKind::TimerReload, time + 4);
+                }
+                self.schedule_timer_events(time);
+            },
+            EventKind::TimerReload => {
+                if self.timer.reload(time) {
+                    self.request_interrupt(Interrupt::Timer);
+                }
+                self.schedule_timer_events(time);
+            },
+            EventKind::FrameSequencer => {
+                self.sync_apu(time);
+                self.apu.step_frame_sequencer();
+                self.schedule_timer_events(time);
+            },
+            EventKind::SerialBit => {
+                // Nothing is connected, so a 1 is shifted in for every bit sent
+                self.io[0x01] = (self.io[0x01] << 1) | 0x01;
+                self.serial_bits -= 1;
+
+                if self.serial_bits == 0 {
+                    self.io[0x02] &= 0x7F;
+                    self.request_interrupt(Interrupt::Serial);
+                } else {
+                    self.scheduler.schedule(EventKind::SerialBit, time + SERIAL_BIT_CYCLES);
+                }
+            },
+            EventKind::DmaDone => self.sync_dma(time),
+        }
+    }
+
+    /// Schedules the next TIMA overflow and frame sequencer step, which both
+    /// depend on the timer state
+    fn schedule_timer_events(&mut self, now: u64) {
+        match self.timer.next_overflow(now) {
+            Some(at) => self.scheduler.schedule(EventKind::TimerOverflow, at),
+            None => self.scheduler.cancel(EventKind::TimerOverflow),
+        }
+
+        self.scheduler.schedule(EventKind::FrameSequencer, self.timer.next_frame_sequencer_clock(now));
+    }
+
+    fn sync_apu(&mut self, now: u64) {
+        // Frame sequencer events keep this below 8192 cycles
+        self.apu.tick((now - self.apu_synced_at) as u32);
+        self.apu_synced_at = now;
+    }
+
+    fn sync_ppu(&mut self, now: u64) {
+        let cycles = (now - self.ppu_synced_at).min(u16::MAX as u64) as u16;
+        self.ppu_synced_at = now;
+
+        let update = self.ppu.step(cycles);
+        if update.vblank_interrupt {
+            self.request_interrupt(Interrupt::VBlank);
+        }
+        if update.stat_interrupt {
+            self.request_interrupt(Interrupt::LCDStat);
+        }
+        self.frame_ready |= update.frame_ready;
+
+        match self.ppu.cycles_until_event() {
+            Some(cycles) => self.scheduler.schedule(EventKind::PpuMode, now + cycles as u64),
+            None => self.scheduler.cancel(EventKind::PpuMode),
+        }
+    }
+
+    /// Copies the bytes the running DMA has transferred by `now`
+    fn sync_dma(&mut self, now: u64) {
+        let Some(dma) = &self.dma else {
+            return;
+        };
+        let (source, copied) = (dma.source, dma.copied);
+
+        let elapsed = (now - dma.started_at).saturating_sub(DMA_STARTUP_CYCLES);
+        let due = (elapsed / DMA_BYTE_CYCLES).min(0xA0) as u16;
+
+        for i in copied..due {
+            // Unlike the CPU, the DMA isn't locked out of VRAM
+            let byte = match source + i {
+                addr @ 0x8000..=0x9FFF => self.ppu.read_vram(addr),
+                addr => self.read8(addr),
+            };
+            self.ppu.write_oam(0xFE00 + i, byte);
         }
-        if let Some(tima) = update.new_tima {
-            self.write8(TimerAddr::TIMA as u16, tima);
+
+        if due == 0xA0 {
+            self.dma = None;
+            self.scheduler.cancel(EventKind::DmaDone);
+        } else if let Some(dma) = &mut self.dma {
+            dma.copied = due;
         }
-        if update.timer_interrupt {
-            self.request_interrupt(Interrupt::Timer);
+    }
+
+    /// Lets the cartridge clock (if any) catch up to `now`
+    fn sync_cartridge(&mut self, now: u64) {
+        let mut cycles = now - self.cartridge_synced_at;
+        self.cartridge_synced_at = now;
+
+        while cycles > 0 {
+            let chunk = cycles.min(u32::MAX as u64);
+            self.cartridge.tick(chunk as u32);
+            cycles -= chunk;
         }
     }
 
-    pub fn tick_apu(&mut self, cycles: u8) {
-        self.apu.tick(cycles as u32);
+    /// Drains the bytes sent over the serial port since the last call
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.serial_output)
+    }
+
+    /// Returns whether the PPU completed a frame since the last call
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.ppu.set_renderer(renderer);
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.ppu.framebuffer
     }
     
     pub fn get_audio_samples(&mut self) -> (Vec<f32>, Vec<f32>) {
+        self.sync_apu(self.scheduler.now());
+
         (std::mem::take(&mut self.apu.sample_buffer_l), 
          std::mem::take(&mut self.apu.sample_buffer_r))
     }
@@ -329,24 +559,35 @@ impl Mmu {
         self.io[0x0F] |= 1 << (interrupt as u8);
     }
 
-    pub fn write_ly(&mut self, value: u8) {
-        self.io[0x44] = value;
+    pub fn has_battery(&self) -> bool {
+        self.cartridge.has_battery()
     }
 
-    pub fn write_div(&mut self, value: u8) {
-        self.io[0x04] = value;
+    pub fn cartridge_header(&self) -> &CartridgeHeader {
+        self.cartridge.header()
     }
 
-    fn ram_size_from_header(value: u8) -> usize {
-        match value {
-            0x00 => 0,
-            0x01 => 2 * 1024,
-            0x02 => 8 * 1024,
-            0x03 => 32 * 1024,
-            0x04 => 128 * 1024,
-            0x05 => 64 * 1024,
-            _ => 0,
-        }
+    /// Battery backed state of the cartridge in `.sav` layout
+    pub fn save_data(&mut self) -> Option<Vec<u8>> {
+        self.sync_cartridge(self.scheduler.now());
+        self.cartridge.save_data()
+    }
+
+    /// Restores the battery backed state of the cartridge
+    ///
+    /// * `result` - Returns false if the save did not match the cartridge
+    pub fn load_save_data(&mut self, data: &[u8]) -> bool {
+        self.eram_dirty = false;
+        self.cartridge.load_save_data(data)
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.cartridge.rumble()
+    }
+
+    /// Returns whether external RAM was written since the last call
+    pub fn take_eram_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.eram_dirty)
     }
 
     pub fn key_down(&mut self, key: Key) {
@@ -358,12 +599,176 @@ impl Mmu {
     pub fn key_up(&mut self, key: Key) {
         self.joypad.release(key);
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::cartridge::test_rom;
+
+    fn mmu() -> Mmu {
+        Mmu::new(Cartridge::new(test_rom(2, 0x00)).unwrap(), Apu::new(), Timer::new(), Ppu::new())
+    }
+
+    #[test]
+    fn boot_rom_starts_with_the_lcd_off() {
+        let mut mmu = mmu();
+        mmu.map_boot_rom(vec![0; 0x100]);
+
+        assert_eq!(mmu.read8(0xFF40), 0x00);
+        assert_eq!(mmu.read8(0xFF43), 0x00);
+        assert_eq!(mmu.read8(0xFF44), 0x00);
+        // Unused bit 7, LY == LYC and mode 0
+        assert_eq!(mmu.read8(0xFF41), 0x84);
+    }
 
-    fn is_mbc1(&self) -> bool {
-        matches!(self.cartridge_type, 0x01 | 0x02 | 0x03)
+    #[test]
+    fn div_after_boot() {
+        for (model, div) in [(Model::Dmg0, 0x18), (Model::Dmg, 0xAB), (Model::Mgb, 0xAB), (Model::Sgb, 0xD8), (Model::Cgb, 0x26)] {
+            let mut mmu = mmu();
+            mmu.post_boot(model);
+            assert_eq!(mmu.read8(0xFF04), div, "{:?}", model);
+        }
     }
 
-    fn is_mbc3(&self) -> bool {
-        matches!(self.cartridge_type, 0x0F | 0x10 | 0x11 | 0x12 | 0x13)
+    #[test]
+    fn timer_interrupt_comes_with_the_reload() {
+        let mut mmu = mmu();
+        mmu.write8(0xFF0F, 0x00);
+        mmu.write8(0xFF06, 0x42);
+        mmu.write8(0xFF05, 0xFF);
+        mmu.write8(0xFF07, 0x05);
+
+        mmu.tick(16);
+        assert_eq!(mmu.read8(0xFF05), 0x00);
+        assert_eq!(mmu.read8(0xFF0F) & 0x04, 0);
+
+        mmu.tick(4);
+        assert_eq!(mmu.read8(0xFF05), 0x42);
+        assert_eq!(mmu.read8(0xFF0F) & 0x04, 0x04);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn apu_registers_read_back_through_their_masks() {
+        let mut mmu = mmu();
+        for addr in io::APU_REGISTERS {
+            mmu.write8(addr, 0x00);
+            assert_eq!(mmu.read8(addr), io::READ_MASKS[(addr - 0xFF00) as usize], "{:04X}", addr);
+        }
+        for addr in io::APU_REGISTERS {
+            mmu.write8(addr, 0xFF);
+            assert_eq!(mmu.read8(addr), 0xFF, "{:04X}", addr);
+        }
+
+        mmu.write8(0xFF07, 0x00);
+        assert_eq!(mmu.read8(0xFF07), 0xF8);
+        mmu.write8(0xFF0F, 0x00);
+        assert_eq!(mmu.read8(0xFF0F), 0xE0);
+    }
+
+    #[test]
+    fn apu_power_off_clears_the_registers() {
+        let mut mmu = mmu();
+        for addr in io::APU_REGISTERS {
+            mmu.write8(addr, 0xFF);
+        }
+        assert_eq!(mmu.read8(0xFF26), 0xFF);
+
+        mmu.write8(0xFF26, 0x00);
+        assert_eq!(mmu.read8(0xFF26), 0x70);
+        for addr in io::APU_REGISTERS {
+            assert_eq!(mmu.read8(addr), io::READ_MASKS[(addr - 0xFF00) as usize], "{:04X}", addr);
+        }
+
+        // Writes are ignored until the APU is switched back on
+        mmu.write8(0xFF24, 0x77);
+        assert_eq!(mmu.read8(0xFF24), 0x00);
+        mmu.write8(0xFF26, 0x80);
+        assert_eq!(mmu.read8(0xFF26), 0xF0);
+        mmu.write8(0xFF24, 0x77);
+        assert_eq!(mmu.read8(0xFF24), 0x77);
+    }
+
+    #[test]
+    fn length_writes_while_the_apu_is_off() {
+        let mut mmu = mmu();
+        mmu.write8(0xFF26, 0x00);
+
+        // A length of 1 for every channel, the duty bits are not written
+        mmu.write8(0xFF11, 0xFF);
+        mmu.write8(0xFF16, 0xFF);
+        mmu.write8(0xFF1B, 0xFF);
+        mmu.write8(0xFF20, 0xFF);
+        assert_eq!(mmu.read8(0xFF11), 0x3F);
+        assert_eq!(mmu.read8(0xFF16), 0x3F);
+
+        mmu.write8(0xFF26, 0x80);
+        for (dac, trigger, value) in [(0xFF12, 0xFF14, 0xF0), (0xFF17, 0xFF19, 0xF0), (0xFF1A, 0xFF1E, 0x80), (0xFF21, 0xFF23, 0xF0)] {
+            mmu.write8(dac, value);
+            mmu.write8(trigger, 0xC0);
+        }
+        assert_eq!(mmu.read8(0xFF26), 0xFF);
+
+        // Any two frame sequencer steps contain a length clock
+        mmu.apu.step_frame_sequencer();
+        mmu.apu.step_frame_sequencer();
+        assert_eq!(mmu.read8(0xFF26), 0xF0);
+    }
+
+    #[test]
+    fn oam_is_locked_during_oam_scan() {
+        let mut mmu = mmu();
+        mmu.write8(0xFE00, 0x12);
+        assert_eq!(mmu.read8(0xFE00), 0xFF);
+
+        mmu.write8(0x8000, 0x34);
+        assert_eq!(mmu.read8(0x8000), 0x34);
+    }
+
+    #[test]
+    fn vram_and_oam_are_locked_while_drawing() {
+        let mut mmu = mmu();
+        mmu.write8(0x8000, 0x34);
+        mmu.tick(84);
+
+        mmu.write8(0x8000, 0x56);
+        mmu.write8(0xFE00, 0x12);
+        assert_eq!(mmu.read8(0x8000), 0xFF);
+        assert_eq!(mmu.read8(0xFE00), 0xFF);
+
+        // Debug reads still see what is there
+        assert_eq!(mmu.peek8(0x8000), 0x34);
+        assert_eq!(mmu.peek8(0xFE00), 0x00);
+
+        // Both are free again in HBlank, the writes above were dropped
+        mmu.tick(200);
+        assert_eq!(mmu.read8(0x8000), 0x34);
+        assert_eq!(mmu.read8(0xFE00), 0x00);
+    }
+
+    #[test]
+    fn lcd_off_allows_every_access() {
+        let mut mmu = mmu();
+        mmu.write8(0xFF40, 0x00);
+
+        for _ in 0..200 {
+            mmu.tick(4);
+            mmu.write8(0x8000, 0x34);
+            mmu.write8(0xFE00, 0x12);
+            assert_eq!(mmu.read8(0x8000), 0x34);
+            assert_eq!(mmu.read8(0xFE00), 0x12);
+        }
+    }
+
+    #[test]
+    fn unrestricted_access_ignores_the_ppu_mode() {
+        let mut mmu = mmu();
+        mmu.set_restrict_access(false);
+        mmu.tick(84);
+
+        mmu.write8(0x8000, 0x34);
+        mmu.write8(0xFE00, 0x12);
+        assert_eq!(mmu.read8(0x8000), 0x34);
+        assert_eq!(mmu.read8(0xFE00), 0x12);
+    }
+}
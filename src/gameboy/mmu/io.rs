@@ -0,0 +1,66 @@
+/// Bits of every I/O register 0xFF00-0xFF7F that always read back as 1 on
+/// a DMG. Unused bits, write-only registers (like NR13) and unmapped
+/// addresses are 1s, so reading them never shows what was written.
+pub const READ_MASKS: [u8; 0x80] = [
+    // P1    SB    SC    --    DIV   TIMA  TMA   TAC
+    0xC0, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8,
+    // --    --    --    --    --    --    --    IF
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+    // NR10  NR11  NR12  NR13  NR14  --    NR21  NR22
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00,
+    // NR23  NR24  NR30  NR31  NR32  NR33  NR34  --
+    0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    // NR41  NR42  NR43  NR44  NR50  NR51  NR52  --
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF,
+    // --
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // LCDC  STAT  SCY   SCX   LY    LYC   DMA   BGP
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // OBP0  OBP1  WY    WX    CGB only from here on
+    0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+    // BOOT
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+/// Bits of every I/O register 0xFF00-0xFF7F a write can change. Read-only
+/// and unused bits keep their value, registers handled entirely by their
+/// component (DIV, LY, ...) and unmapped addresses are all 0s.
+pub const WRITE_MASKS: [u8; 0x80] = [
+    // P1    SB    SC    --    DIV   TIMA  TMA   TAC
+    0x30, 0xFF, 0x81, 0x00, 0x00, 0xFF, 0xFF, 0x07,
+    // --    --    --    --    --    --    --    IF
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F,
+    // NR10  NR11  NR12  NR13  NR14  --    NR21  NR22
+    0x7F, 0xFF, 0xFF, 0xFF, 0xC7, 0x00, 0xFF, 0xFF,
+    // NR23  NR24  NR30  NR31  NR32  NR33  NR34  --
+    0xFF, 0xC7, 0x80, 0xFF, 0x60, 0xFF, 0xC7, 0x00,
+    // NR41  NR42  NR43  NR44  NR50  NR51  NR52  --
+    0x3F, 0xFF, 0xFF, 0xC0, 0xFF, 0xFF, 0x80, 0x00,
+    // --
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // Wave RAM
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // LCDC  STAT  SCY   SCX   LY    LYC   DMA   BGP
+    0xFF, 0x78, 0xFF, 0xFF, 0x00, 0xFF, 0xFF, 0xFF,
+    // OBP0  OBP1  WY    WX    CGB only from here on
+    0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00,
+    // BOOT
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// NR10-NR51, which ignore writes while the APU is switched off
+pub const APU_REGISTERS: std::ops::RangeInclusive<u16> = 0xFF10..=0xFF25;
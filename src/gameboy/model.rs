@@ -0,0 +1,203 @@
+use crate::rom::{CartridgeHeader, Licensee};
+
+/// The console revision being emulated
+///
+/// Only the state the boot ROM leaves behind differs between the models
+/// for now. A CGB runs every game in DMG compatibility mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Model {
+    /// Early DMG with the first boot ROM revision
+    Dmg0,
+    #[default]
+    Dmg,
+    /// Game Boy Pocket
+    Mgb,
+    /// Super Game Boy
+    Sgb,
+    Sgb2,
+    /// Game Boy Color
+    Cgb,
+}
+
+impl Model {
+    pub const ALL: [Model; 6] = [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Sgb, Model::Sgb2, Model::Cgb];
+
+    pub fn from_name(name: &str) -> Option<Model> {
+        let name = name.to_ascii_lowercase();
+        Self::ALL.into_iter().find(|model| model.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Model::Dmg0 => "dmg0",
+            Model::Dmg  => "dmg",
+            Model::Mgb  => "mgb",
+            Model::Sgb  => "sgb",
+            Model::Sgb2 => "sgb2",
+            Model::Cgb  => "cgb",
+        }
+    }
+
+    /// AF, BC, DE and HL when the boot ROM jumps to 0x0100
+    pub fn boot_registers(self, header: &CartridgeHeader) -> [u16; 4] {
+        // The DMG and MGB boot ROMs leave the flags of the last addition of
+        // the header check behind: the sum of 0x0134-0x014C plus 0x19 (the
+        // negated computed checksum) plus the checksum byte, which is 0 for a
+        // valid header
+        let sum = header.computed_header_checksum.wrapping_neg();
+        let (result, carry) = sum.overflowing_add(header.header_checksum);
+        let half_carry = (sum & 0x0F) + (header.header_checksum & 0x0F) > 0x0F;
+        let checksum_flags = (if result == 0 { 0x80 } else { 0 })
+            | (if half_carry { 0x20 } else { 0 })
+            | (if carry { 0x10 } else { 0 });
+
+        match self {
+            Model::Dmg0 => [0x0100, 0xFF13, 0x00C1, 0x8403],
+            Model::Dmg  => [0x0100 | checksum_flags, 0x0013, 0x00D8, 0x014D],
+            Model::Mgb  => [0xFF00 | checksum_flags, 0x0013, 0x00D8, 0x014D],
+            Model::Sgb  => [0x0100, 0x0014, 0x0000, 0xC060],
+            Model::Sgb2 => [0xFF00, 0x0014, 0x0000, 0xC060],
+            Model::Cgb  => {
+                // The CGB boot ROM picks a compatibility palette from the
+                // title checksum of Nintendo games and leaves it in B
+                let nintendo = matches!(&header.licensee, Licensee::Old(0x01))
+                    || matches!(&header.licensee, Licensee::New(code) if code == "01");
+                let b = if nintendo { header.title_checksum } else { 0x00 };
+                let hl = if b == 0x43 || b == 0x58 { 0x991A } else { 0x007C };
+
+                [0x1180, (b as u16) << 8, 0x0008, hl]
+            },
+        }
+    }
+
+    /// The internal 16 bit counter behind DIV when the boot ROM jumps to
+    /// 0x0100. The SGB and CGB boot ROMs run for a varying amount of time,
+    /// so their values are typical ones rather than exact.
+    pub fn boot_div_counter(self) -> u16 {
+        match self {
+            Model::Dmg0 => 0x1830,
+            Model::Dmg | Model::Mgb => 0xABCC,
+            Model::Sgb | Model::Sgb2 => 0xD85C,
+            Model::Cgb => 0x267C,
+        }
+    }
+
+    /// I/O registers when the boot ROM jumps to 0x0100, in the order they
+    /// have to be written. DIV and DMA are not part of this as writing them
+    /// has side effects, STAT and LY follow from the PPU state.
+    pub fn boot_io_registers(self) -> Vec<(u16, u8)> {
+        let sc = if self == Model::Cgb { 0x7F } else { 0x7E };
+        // Only the SGB boot ROMs don't play the chime on channel 1
+        let nr52 = if matches!(self, Model::Sgb | Model::Sgb2) { 0xF0 } else { 0xF1 };
+
+        vec![
+            (0xFF00, 0xCF), // P1
+            (0xFF01, 0x00), // SB
+            (0xFF02, sc),   // SC
+            (0xFF05, 0x00), // TIMA
+            (0xFF06, 0x00), // TMA
+            (0xFF07, 0xF8), // TAC
+            (0xFF0F, 0xE1), // IF
+
+            // The APU has to be switched on before its registers take writes
+            (0xFF26, nr52), // NR52
+            (0xFF10, 0x80), // NR10
+            (0xFF11, 0xBF), // NR11
+            (0xFF12, 0xF3), // NR12
+            (0xFF13, 0xC1), // NR13, last note of the chime
+            (0xFF14, 0x07), // NR14
+            (0xFF16, 0x3F), // NR21
+            (0xFF17, 0x00), // NR22
+            (0xFF19, 0x00), // NR24
+            (0xFF1A, 0x7F), // NR30
+            (0xFF1C, 0x9F), // NR32
+            (0xFF1E, 0x00), // NR34
+            (0xFF21, 0x00), // NR42
+            (0xFF22, 0x00), // NR43
+            (0xFF24, 0x77), // NR50
+            (0xFF25, 0xF3), // NR51
+
+            (0xFF40, 0x91), // LCDC
+            (0xFF42, 0x00), // SCY
+            (0xFF43, 0x00), // SCX
+            (0xFF45, 0x00), // LYC
+            (0xFF47, 0xFC), // BGP
+            (0xFF4A, 0x00), // WY
+            (0xFF4B, 0x00), // WX
+        ]
+    }
+
+    /// DMA when the boot ROM jumps to 0x0100. Writing it would start a transfer.
+    pub fn boot_dma_register(self) -> u8 {
+        if self == Model::Cgb { 0x00 } else { 0xFF }
+    }
+
+    /// Wave RAM after boot. It comes up random on the other models and
+    /// none of their boot ROMs touch it.
+    pub fn boot_wave_ram(self) -> Option<[u8; 16]> {
+        match self {
+            Model::Cgb => Some([0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF]),
+            _ => None,
+        }
+    }
+
+    /// Whether the boot ROM played its chime on channel 1, which stays
+    /// enabled with a faded out envelope afterwards
+    pub fn plays_boot_chime(self) -> bool {
+        !matches!(self, Model::Sgb | Model::Sgb2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::cartridge::test_rom;
+
+    /// Header with a valid header checksum of `checksum`, made to fit
+    /// through the version byte
+    fn header(title: &[u8], licensee: u8, checksum: u8) -> CartridgeHeader {
+        let mut rom = test_rom(2, 0x00);
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title);
+        rom[0x014B] = licensee;
+        rom[0x014C] = 0x00;
+        rom[0x014C] = CartridgeHeader::parse(&rom).unwrap().computed_header_checksum.wrapping_sub(checksum);
+        rom[0x014D] = checksum;
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.header_checksum_valid);
+        header
+    }
+
+    #[test]
+    fn dmg_registers() {
+        assert_eq!(Model::Dmg.boot_registers(&header(b"TETRIS", 0x01, 0x0A)), [0x01B0, 0x0013, 0x00D8, 0x014D]);
+        assert_eq!(Model::Mgb.boot_registers(&header(b"TETRIS", 0x01, 0x0A)), [0xFFB0, 0x0013, 0x00D8, 0x014D]);
+        assert_eq!(Model::Dmg0.boot_registers(&header(b"TETRIS", 0x01, 0x0A)), [0x0100, 0xFF13, 0x00C1, 0x8403]);
+    }
+
+    #[test]
+    fn dmg_flags_come_from_the_header_check() {
+        // Nothing carries when adding 0
+        assert_eq!(Model::Dmg.boot_registers(&header(b"", 0x00, 0x00))[0], 0x0180);
+        // No carry out of the lower nibble
+        assert_eq!(Model::Dmg.boot_registers(&header(b"", 0x00, 0x20))[0], 0x0190);
+
+        // A broken checksum leaves Z clear (the real boot ROM would hang)
+        let mut header = header(b"", 0x00, 0x0A);
+        header.header_checksum = 0x0B;
+        assert_eq!(Model::Dmg.boot_registers(&header)[0], 0x0130);
+    }
+
+    #[test]
+    fn cgb_registers() {
+        assert_eq!(Model::Cgb.boot_registers(&header(b"TETRIS", 0x00, 0x0A)), [0x1180, 0x0000, 0x0008, 0x007C]);
+
+        // Nintendo games get their title checksum in B
+        let nintendo = header(b"TETRIS", 0x01, 0x0A);
+        let b = nintendo.title_checksum as u16;
+        assert_eq!(Model::Cgb.boot_registers(&nintendo), [0x1180, b << 8, 0x0008, 0x007C]);
+
+        // Two checksums also change HL
+        assert_eq!(Model::Cgb.boot_registers(&header(b"C", 0x01, 0x0A)), [0x1180, 0x4300, 0x0008, 0x991A]);
+    }
+}
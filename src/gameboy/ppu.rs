@@ -1,4 +1,44 @@
-use super::{cpu::Interrupt, screen::framebuffer::Framebuffer, mmu::Mmu};
+mod fifo;
+
+use super::screen::framebuffer::{Framebuffer, DMG_PALETTE};
+use fifo::Fifo;
+
+/// Dots of every line, visible or not
+const LINE_DOTS: u16 = 456;
+/// Dots mode 2 searches OAM for the sprites on the line
+const OAM_SCAN_DOTS: u16 = 80;
+/// Dots mode 3 takes without fine scroll, window and sprites
+const MIN_DRAWING_DOTS: u16 = 172;
+/// Sprites the OAM scan picks per line at most
+const MAX_LINE_SPRITES: usize = 10;
+/// Dots the fetcher needs to switch over to the window
+const WINDOW_PENALTY: u16 = 6;
+/// Dots each sprite fetch takes at least
+const SPRITE_PENALTY: u16 = 6;
+/// Dots a sprite at OAM X 0 costs, independent of the scroll position
+const SPRITE_PENALTY_X0: u16 = 11;
+
+/// How the picture is produced
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Renderer {
+    /// Draws every line in one go when mode 3 ends. Fast, but register
+    /// changes during mode 3 only show up on the next line.
+    #[default]
+    Scanline,
+    /// Background/window and sprite fetchers feeding a pixel FIFO, one dot at
+    /// a time like the hardware, so mid-line effects work
+    Fifo,
+}
+
+impl Renderer {
+    pub fn from_name(name: &str) -> Option<Renderer> {
+        match &name.to_ascii_lowercase()[..] {
+            "scanline" => Some(Renderer::Scanline),
+            "fifo" => Some(Renderer::Fifo),
+            _ => None,
+        }
+    }
+}
 
 #[derive(PartialEq, Clone, Copy)]
 pub enum PpuMode {
@@ -8,142 +48,452 @@ pub enum PpuMode {
     Drawing,
 }
 
-pub struct Ppu {
+/// What happened during `Ppu::step` that the rest of the system has to know about
+pub struct PpuUpdate {
+    pub vblank_interrupt: bool,
+    pub stat_interrupt: bool,
     pub frame_ready: bool,
+}
+
+/// Where the window shows up on the current line
+#[derive(Clone, Copy)]
+struct WindowStart {
+    /// First screen column showing the window
+    x: u8,
+    /// Window pixels cut off on the left, for WX below 7
+    skip: u8,
+}
+
+/// An OAM entry picked for the current line
+#[derive(Clone, Copy)]
+struct Sprite {
+    /// Screen position plus 16
+    y: u8,
+    /// Screen position plus 8
+    x: u8,
+    tile: u8,
+    flags: u8,
+}
+
+pub struct Ppu {
     pub framebuffer: Framebuffer,
+    vram: [u8; 0x2000],
+    oam:  [u8; 0xA0],
+
+    lcdc: u8,
+    /// Only the interrupt select bits 3-6, the rest is derived from the state
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+
     mode: PpuMode,
     scanline: u8,
+    /// Window row to draw next. Only advances on lines that showed the window.
+    window_line: u8,
+    /// LY matched WY at some point in this frame, the window may show up from now on
+    wy_triggered: bool,
+    /// The window was drawn on the current line
+    window_drawn: bool,
+    /// With WX 166 the window fills the whole next line
+    window_fills_line: bool,
     dot_counter: u16,
+    /// Length of mode 3 on the current line, HBlank gets the rest
+    drawing_dots: u16,
     bg_color_index: [u8; 160],
+    /// Sprites the OAM scan picked for the current line, in OAM order
+    line_sprites: Vec<Sprite>,
+
+    renderer: Renderer,
+    fifo: Fifo,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Ppu {
     pub fn new() -> Self {
         Self {
             framebuffer: Framebuffer::new(),
+            vram: [0; 0x2000],
+            oam: [0; 0xA0],
+
+            lcdc: 0xFF,
+            stat: 0x00,
+            scy: 0xFF,
+            scx: 0xFF,
+            lyc: 0xFF,
+            bgp: 0xFF,
+            obp0: 0xFF,
+            obp1: 0xFF,
+            wy: 0xFF,
+            wx: 0xFF,
+
             mode: PpuMode::OamScan,
             scanline: 0,
-            frame_ready: false,
+            window_line: 0,
+            wy_triggered: false,
+            window_drawn: false,
+            window_fills_line: false,
             dot_counter: 0,
+            drawing_dots: MIN_DRAWING_DOTS,
             bg_color_index: [0; 160],
+            line_sprites: Vec::with_capacity(MAX_LINE_SPRITES),
+
+            renderer: Renderer::default(),
+            fifo: Fifo::new(),
         }
     }
 
-    pub fn step(&mut self, cycles: u16, mmu: &mut Mmu) {
-        if !self.lcd_enabled(mmu) {
-            self.reset(mmu);
-            return;
+    /// Puts the registers into their power on state: all 0, so the LCD stays
+    /// off until the boot ROM switches it on
+    pub fn power_on(&mut self) {
+        self.lcdc = 0x00;
+        self.stat = 0x00;
+        self.scy = 0x00;
+        self.scx = 0x00;
+        self.lyc = 0x00;
+        self.bgp = 0x00;
+        self.obp0 = 0x00;
+        self.obp1 = 0x00;
+        self.wy = 0x00;
+        self.wx = 0x00;
+        self.reset();
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    /// Whether the picture currently depends on the exact time of register,
+    /// VRAM and OAM writes, so the PPU has to be caught up before each of them
+    pub fn renders_per_dot(&self) -> bool {
+        self.renderer == Renderer::Fifo && self.mode == PpuMode::Drawing && self.lcd_enabled()
+    }
+
+    /// The PPU holds VRAM during mode 3, the CPU can't reach it then
+    pub fn vram_accessible(&self) -> bool {
+        !self.lcd_enabled() || self.mode != PpuMode::Drawing
+    }
+
+    /// The PPU holds OAM during modes 2 and 3, the CPU can't reach it then
+    pub fn oam_accessible(&self) -> bool {
+        !self.lcd_enabled() || !matches!(self.mode, PpuMode::OamScan | PpuMode::Drawing)
+    }
+
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        self.vram[(addr - 0x8000) as usize]
+    }
+
+    pub fn write_vram(&mut self, addr: u16, value: u8) {
+        self.vram[(addr - 0x8000) as usize] = value;
+    }
+
+    pub fn read_oam(&self, addr: u16) -> u8 {
+        self.oam[(addr - 0xFE00) as usize]
+    }
+
+    pub fn write_oam(&mut self, addr: u16, value: u8) {
+        self.oam[(addr - 0xFE00) as usize] = value;
+    }
+
+    /// Reads one of the LCD registers 0xFF40-0xFF45 and 0xFF47-0xFF4B
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF40 => self.lcdc,
+            0xFF41 => {
+                let coincidence = if self.scanline == self.lyc { 0x04 } else { 0x00 };
+                // The mode reads as 0 while the LCD is off
+                let mode = if self.lcd_enabled() { self.mode as u8 } else { 0 };
+
+                self.stat | coincidence | mode
+            },
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.scanline,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes one of the LCD registers 0xFF40-0xFF45 and 0xFF47-0xFF4B
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF40 => self.lcdc = value,
+            0xFF41 => self.stat = value & 0b0111_1000,
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            // LY is read only
+            0xFF44 => {},
+            0xFF45 => self.lyc = value,
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            _ => {},
+        }
+    }
+
+    pub fn step(&mut self, cycles: u16) -> PpuUpdate {
+        let mut update = PpuUpdate {
+            vblank_interrupt: false,
+            stat_interrupt: false,
+            frame_ready: false,
+        };
+
+        if !self.lcd_enabled() {
+            self.reset();
+            return update;
         }
     
+        if self.renderer == Renderer::Fifo && self.mode == PpuMode::Drawing {
+            self.run_fifo(cycles.min(self.drawing_dots.saturating_sub(self.dot_counter)));
+        }
+
         self.dot_counter += cycles;
     
         match self.mode {
-            PpuMode::OamScan if self.dot_counter >= 80 => {
-                self.dot_counter -= 80;
-                self.enter_mode(PpuMode::Drawing, mmu);
+            PpuMode::OamScan if self.dot_counter >= OAM_SCAN_DOTS => {
+                self.dot_counter -= OAM_SCAN_DOTS;
+                if self.scanline == self.wy {
+                    self.wy_triggered = true;
+                }
+                self.window_drawn = false;
+                self.scan_oam();
+                self.drawing_dots = self.drawing_length();
+                self.enter_mode(PpuMode::Drawing, &mut update);
+
+                if self.renderer == Renderer::Fifo {
+                    self.start_fifo_line();
+                    self.run_fifo(self.dot_counter);
+                }
             }
-            PpuMode::Drawing if self.dot_counter >= 172 => {
-                self.dot_counter -= 172;
-                self.render_scanline(mmu);
-                self.render_sprites_scanline(mmu);
-                self.enter_mode(PpuMode::HBlank, mmu);
+            PpuMode::Drawing if self.dot_counter >= self.drawing_dots => {
+                self.dot_counter -= self.drawing_dots;
+                match self.renderer {
+                    Renderer::Scanline => {
+                        self.render_scanline();
+                        self.render_sprites_scanline();
+                    },
+                    Renderer::Fifo => self.finish_fifo_line(),
+                }
+                self.finish_window_line();
+                self.enter_mode(PpuMode::HBlank, &mut update);
             }
-            PpuMode::HBlank if self.dot_counter >= 204 => {
-                self.dot_counter -= 204;
-                self.advance_scanline(mmu);
+            PpuMode::HBlank if self.dot_counter >= self.mode_length() => {
+                self.dot_counter -= self.mode_length();
+                self.advance_scanline(&mut update);
             }
-            PpuMode::VBlank if self.dot_counter >= 456 => {
-                self.dot_counter -= 456;
-                self.advance_scanline(mmu);
+            PpuMode::VBlank if self.dot_counter >= LINE_DOTS => {
+                self.dot_counter -= LINE_DOTS;
+                self.advance_scanline(&mut update);
             }
             _ => {}
         }
+
+        update
+    }
+
+    /// Dots until the current mode ends, the only point where `step` changes
+    /// anything visible
+    ///
+    /// * `result` - Returns None while the LCD is off
+    pub fn cycles_until_event(&self) -> Option<u16> {
+        if !self.lcd_enabled() {
+            return None;
+        }
+
+        Some(self.mode_length().saturating_sub(self.dot_counter))
+    }
+
+    /// Dots the current mode takes in total
+    fn mode_length(&self) -> u16 {
+        match self.mode {
+            PpuMode::OamScan => OAM_SCAN_DOTS,
+            PpuMode::Drawing => self.drawing_dots,
+            PpuMode::HBlank  => LINE_DOTS - OAM_SCAN_DOTS - self.drawing_dots,
+            PpuMode::VBlank  => LINE_DOTS,
+        }
+    }
+
+    /// Length of mode 3 on the current line. The fetcher has to drop the
+    /// SCX % 8 pixels scrolled out on the left, restart for the window, and
+    /// stall for every sprite (see the Pandocs on mode 3 length).
+    fn drawing_length(&self) -> u16 {
+        let mut dots = MIN_DRAWING_DOTS + (self.scx & 0x07) as u16;
+
+        let window_x = self.window_start().map(|window| window.x);
+        if window_x.is_some() {
+            dots += WINDOW_PENALTY;
+        }
+
+        if self.lcdc & 0x02 == 0 {
+            return dots;
+        }
+
+        let mut sprites = self.line_sprites.clone();
+        // Sprites are fetched from left to right, ties in OAM order
+        sprites.sort_by_key(|sprite| sprite.x);
+
+        // Background/window tiles whose fetch a sprite already waited for
+        let mut waited_tiles = Vec::new();
+
+        for sprite in sprites {
+            // Right of the screen the output never reaches the sprite, so it is not fetched
+            if sprite.x >= 168 {
+                continue;
+            }
+
+            if sprite.x == 0 {
+                dots += SPRITE_PENALTY_X0;
+                continue;
+            }
+
+            let screen_x = sprite.x as i16 - 8;
+            let (in_window, x) = match window_x {
+                Some(window_x) if screen_x >= window_x as i16 => (true, screen_x - window_x as i16),
+                _ => (false, screen_x + (self.scx & 0x07) as i16),
+            };
+            let (tile_x, pixel) = (x.div_euclid(8), x.rem_euclid(8));
+
+            // The fetch of the tile below the sprite's leftmost pixel has to
+            // finish first, which costs the pixels right of it minus 2
+            if !waited_tiles.contains(&(in_window, tile_x)) {
+                waited_tiles.push((in_window, tile_x));
+                dots += (5 - pixel).max(0) as u16;
+            }
+
+            dots += SPRITE_PENALTY;
+        }
+
+        dots
+    }
+
+    /// Where the window starts on the current line, if it shows up. WX 0-6
+    /// push it off the left edge; with WX 0 the fine scroll of the background
+    /// is cut off the window as well. WX 166 shows only its first pixel, but
+    /// then the whole next line.
+    fn window_start(&self) -> Option<WindowStart> {
+        if self.lcdc & 0x20 == 0 || !self.wy_triggered {
+            return None;
+        }
+
+        if self.window_fills_line {
+            return Some(WindowStart { x: 0, skip: 0 });
+        }
+
+        match self.wx {
+            0 => Some(WindowStart { x: 0, skip: 7 + (self.scx & 0x07) }),
+            1..=6 => Some(WindowStart { x: 0, skip: 7 - self.wx }),
+            7..=166 => Some(WindowStart { x: self.wx - 7, skip: 0 }),
+            _ => None,
+        }
+    }
+
+    /// Moves on to the next window row if this line showed the window
+    fn finish_window_line(&mut self) {
+        self.window_fills_line = self.window_drawn && self.wx == 166;
+
+        if self.window_drawn {
+            self.window_line = self.window_line.wrapping_add(1);
+        }
     }
 
-    fn lcd_enabled(&self, mmu: &Mmu) -> bool {
-        mmu.read8(0xFF40) & 0x80 != 0
+    fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
     }
 
-    fn reset(&mut self, mmu: &mut Mmu) {
+    fn reset(&mut self) {
         self.dot_counter = 0;
         self.mode = PpuMode::OamScan;
         self.scanline = 0;
-        mmu.write_ly(0);
+        self.reset_window();
+    }
+
+    /// The window starts over with every frame
+    fn reset_window(&mut self) {
+        self.window_line = 0;
+        self.wy_triggered = false;
+        self.window_fills_line = false;
     }
 
-    fn enter_mode(&mut self, new_mode: PpuMode, mmu: &mut Mmu) {
+    fn enter_mode(&mut self, new_mode: PpuMode, update: &mut PpuUpdate) {
         if self.mode == new_mode {
             return;
         }
 
         self.mode = new_mode;
-        self.write_stat(mmu);
 
-        let stat = mmu.read8(0xFF41);
         let fire = match new_mode {
-            PpuMode::HBlank  => stat & (1 << 3) != 0,
-            PpuMode::VBlank  => stat & (1 << 4) != 0,
-            PpuMode::OamScan => stat & (1 << 5) != 0,
+            PpuMode::HBlank  => self.stat & (1 << 3) != 0,
+            PpuMode::VBlank  => self.stat & (1 << 4) != 0,
+            PpuMode::OamScan => self.stat & (1 << 5) != 0,
             _ => false,
         };
 
         if fire {
-            mmu.request_interrupt(Interrupt::LCDStat);
+            update.stat_interrupt = true;
         }
     }
 
-    fn write_stat(&self, mmu: &mut Mmu) {
-        let mut stat = mmu.read8(0xFF41);
-
-        let bits = self.mode as u8;
-        stat = (stat & !0b11) | bits;
-
-        mmu.write8(0xFF41, stat);
-    }
-
-    fn render_scanline(&mut self, mmu: &Mmu) {
+    fn render_scanline(&mut self) {
         let ly = self.scanline as u16;
         if ly >= 144 { return; }
 
-        let lcdc = mmu.read8(0xFF40);
+        let lcdc = self.lcdc;
         let bg_enabled = lcdc & 0x01 != 0;
-        let window_enabled = lcdc & 0x20 != 0;
+        let window = self.window_start();
 
-        if !bg_enabled && !window_enabled { return; }
-
-        let scx = mmu.read8(0xFF43) as u16;
-        let scy = mmu.read8(0xFF42) as u16;
-        let wy = mmu.read8(0xFF4A) as u16;
-        let wx = mmu.read8(0xFF4B).wrapping_sub(7) as u16;
+        let scx = self.scx as u16;
+        let scy = self.scy as u16;
+        let window_line = self.window_line as u16;
 
         let tile_data_area = lcdc & 0x10 != 0;
         let bg_map_base = if lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
         let win_map_base = if lcdc & 0x40 != 0 { 0x9C00 } else { 0x9800 };
 
-        let bgp = mmu.read8(0xFF47);
+        let bgp = self.bgp;
 
         for screen_x in 0..160u16 {
-            let use_window = window_enabled && ly >= wy && screen_x >= wx;
-
-            let (map_base, x, y) = 
-                if use_window {
-                    (win_map_base, screen_x - wx, ly - wy)
-                } else {
-                    if !bg_enabled {
-                        self.bg_color_index[screen_x as usize] = 0;
-                        self.framebuffer.pixels[ly as usize][screen_x as usize] = Self::color_index_to_color(0);
-                        continue;
-                    }
-                    (bg_map_base, (screen_x + scx) & 0xFF, (ly + scy) & 0xFF)
-                };
-            
+            let window = window.filter(|window| screen_x >= window.x as u16);
+            if window.is_some() {
+                self.window_drawn = true;
+            }
+
+            // On the DMG, LCDC bit 0 blanks background and window to white,
+            // no matter what BGP says
+            if !bg_enabled {
+                self.bg_color_index[screen_x as usize] = 0;
+                self.framebuffer.pixels[ly as usize][screen_x as usize] = DMG_PALETTE[0];
+                continue;
+            }
+
+            let (map_base, x, y) = match window {
+                Some(window) => (win_map_base, screen_x - window.x as u16 + window.skip as u16, window_line),
+                None => (bg_map_base, (screen_x + scx) & 0xFF, (ly + scy) & 0xFF),
+            };
+
             let tile_x = x / 8;
             let tile_y = y / 8;
             let pixel_x = x % 8;
             let pixel_y = y % 8;
             
             let tile_index_addr = map_base + tile_y * 32 + tile_x;
-            let tile_index = mmu.read8(tile_index_addr);
+            let tile_index = self.read_vram(tile_index_addr);
 
             let tile_addr = if tile_data_area {
                 0x8000 + (tile_index as u16) * 16
@@ -155,8 +505,8 @@ impl Ppu {
 
             let tile_addr = tile_addr + pixel_y * 2;
 
-            let b1 = mmu.read8(tile_addr);
-            let b2 = mmu.read8(tile_addr + 1);
+            let b1 = self.read_vram(tile_addr);
+            let b2 = self.read_vram(tile_addr + 1);
             
             let bit = 7 - pixel_x;
             let color_index = ((b2 >> bit) & 1) << 1 | ((b1 >> bit) & 1);
@@ -167,116 +517,132 @@ impl Ppu {
         }
     }
 
-    fn render_sprites_scanline(&mut self, mmu: &Mmu) {
-        let ly = self.scanline as i16;
+    fn render_sprites_scanline(&mut self) {
+        let ly = self.scanline as usize;
 
         if ly >= 144 { return; }
+        if self.lcdc & 0x02 == 0 { return; }
 
-        let lcdc = mmu.read8(0xFF40);
-        
-        if lcdc & 0x02 == 0 { return; }
+        // The sprite pixel that wins each column: the sprite with the
+        // smallest X, then the one first in OAM. Its BG priority flag alone
+        // decides against the background, even if it loses there.
+        let mut line: [Option<(u8, u8)>; 160] = [None; 160];
 
-        let sprite_height = if lcdc & 0x04 != 0 { 16 } else { 8 };
-        let mut count = 0;
+        let mut sprites = self.line_sprites.clone();
+        sprites.sort_by_key(|sprite| sprite.x);
 
-        for i in 0..40 {
-            let base = 0xFE00 + i * 4;
-            let y = mmu.read8(base) as i16 - 16;
-            let x = mmu.read8(base + 1) as i16 - 8;
-            let mut tile = mmu.read8(base + 2);
-            let flags = mmu.read8(base + 3);
+        for sprite in sprites {
+            let (byte1, byte2) = self.sprite_row(&sprite);
+            let x = sprite.x as i16 - 8;
 
-            if ly < y || ly >= y + sprite_height { continue; }
-            if x <= -8 || x >= 160 { continue; }
+            for px in 0..8 {
+                let framebuffer_x = x + px;
+                if !(0..160).contains(&framebuffer_x) { continue; }
 
-            count += 1;
-            if count > 10 { break; }
+                let color_index = Self::sprite_color_index(byte1, byte2, sprite.flags, px as u8);
+                let slot = &mut line[framebuffer_x as usize];
 
-            if sprite_height == 16 {
-                tile &= 0xFE;
+                if color_index != 0 && slot.is_none() {
+                    *slot = Some((color_index, sprite.flags));
+                }
             }
+        }
 
-            let mut line_in_tile = ly - y;
+        for (x, pixel) in line.into_iter().enumerate() {
+            let Some((color_index, flags)) = pixel else { continue; };
 
-            if flags & 0x40 != 0 { 
-                line_in_tile = sprite_height - 1 - line_in_tile; 
+            if flags & 0x80 != 0 && self.bg_color_index[x] != 0 {
+                continue;
             }
 
-            let actual_tile =
-                if sprite_height == 16 && line_in_tile >= 8 
-                { tile + 1 } else { tile };
+            let obp = if flags & 0x10 != 0 { 
+                self.obp1 
+            } else { 
+                self.obp0 
+            };
             
-            let tile_line = (line_in_tile % 8) as u8;
+            let color = Self::apply_palette(color_index, obp);
+            self.framebuffer.pixels[ly][x] = Self::color_index_to_color(color);
+        }
+    }
 
-            let tile_addr = 0x8000 + actual_tile as u16 * 16 + tile_line as u16 * 2;
-            let byte1 = mmu.read8(tile_addr);
-            let byte2 = mmu.read8(tile_addr + 1);
+    /// Mode 2: picks the first 10 sprites in OAM that overlap the current
+    /// line. Sprites off screen horizontally are picked (and count towards
+    /// the limit) just the same.
+    fn scan_oam(&mut self) {
+        let ly = self.scanline as i16;
+        let sprite_height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+        self.line_sprites.clear();
 
-            for px in 0..8 {
-                let framebuffer_x = x + px;
-                if framebuffer_x < 0 || framebuffer_x >= 160 { continue; }
+        for entry in self.oam.chunks_exact(4) {
+            let sprite = Sprite { y: entry[0], x: entry[1], tile: entry[2], flags: entry[3] };
+            let y = sprite.y as i16 - 16;
 
-                let mut pixel_x = px;
+            if ly < y || ly >= y + sprite_height { continue; }
 
-                if flags & 0x20 != 0 { 
-                    pixel_x = 7 - px; 
-                }
-                
-                let bit = 7 - pixel_x;
-                let color_index = ((byte2 >> bit) & 1) << 1 | ((byte1 >> bit) & 1);
-                if color_index == 0 { continue; }
+            self.line_sprites.push(sprite);
+            if self.line_sprites.len() == MAX_LINE_SPRITES { break; }
+        }
+    }
 
-                if flags & 0x80 != 0 {
-                    if self.bg_color_index[framebuffer_x as usize] != 0 { continue; }
-                }
+    /// Both bit planes of the sprite's tile row on the current line
+    fn sprite_row(&self, sprite: &Sprite) -> (u8, u8) {
+        let sprite_height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+        let mut tile = sprite.tile;
 
-                let obp = if flags & 0x10 != 0 { 
-                    mmu.read8(0xFF49) 
-                } else { 
-                    mmu.read8(0xFF48) 
-                };
-                
-                let color = Self::apply_palette(color_index, obp);
-                self.framebuffer.pixels[ly as usize][framebuffer_x as usize] = Self::color_index_to_color(color);
-            }
+        if sprite_height == 16 {
+            tile &= 0xFE;
+        }
+
+        let mut line_in_tile = self.scanline as i16 - (sprite.y as i16 - 16);
+
+        if sprite.flags & 0x40 != 0 { 
+            line_in_tile = sprite_height - 1 - line_in_tile; 
         }
+
+        let actual_tile =
+            if sprite_height == 16 && line_in_tile >= 8 
+            { tile + 1 } else { tile };
+        
+        let tile_line = (line_in_tile % 8) as u8;
+
+        let tile_addr = 0x8000 + actual_tile as u16 * 16 + tile_line as u16 * 2;
+        (self.read_vram(tile_addr), self.read_vram(tile_addr + 1))
+    }
+
+    /// Colour index of pixel `px` (0 is the left one on screen) of a sprite row
+    fn sprite_color_index(byte1: u8, byte2: u8, flags: u8, px: u8) -> u8 {
+        let pixel_x = if flags & 0x20 != 0 { 7 - px } else { px };
+        let bit = 7 - pixel_x;
+
+        ((byte2 >> bit) & 1) << 1 | ((byte1 >> bit) & 1)
     }
 
-    fn advance_scanline(&mut self, mmu: &mut Mmu) {
+    fn advance_scanline(&mut self, update: &mut PpuUpdate) {
         self.scanline += 1;
 
         if self.scanline > 153 {
             self.scanline = 0;
+            self.reset_window();
         }
 
-        mmu.write_ly(self.scanline);
-        self.check_lyc(mmu);
+        self.check_lyc(update);
 
         if self.scanline == 144 {
-            self.enter_mode(PpuMode::VBlank, mmu);
-            mmu.request_interrupt(Interrupt::VBlank);
-            self.frame_ready = true;
+            self.enter_mode(PpuMode::VBlank, update);
+            update.vblank_interrupt = true;
+            update.frame_ready = true;
         }
 
         if self.scanline < 144 {
-            self.enter_mode(PpuMode::OamScan, mmu);
+            self.enter_mode(PpuMode::OamScan, update);
         }
     }
 
-    fn check_lyc(&self, mmu: &mut Mmu) {
-        let lyc = mmu.read8(0xFF45);
-        let mut stat = mmu.read8(0xFF41);
-
-        if self.scanline == lyc {
-            stat |= 1 << 2;
-            if stat & (1 << 6) != 0 { 
-                mmu.request_interrupt(Interrupt::LCDStat); 
-            }
-        } else {
-            stat &= !(1 << 2);
+    fn check_lyc(&self, update: &mut PpuUpdate) {
+        if self.scanline == self.lyc && self.stat & (1 << 6) != 0 {
+            update.stat_interrupt = true;
         }
-
-        mmu.write8(0xFF41, stat);
     }
 
     fn apply_palette(color_index: u8, palette: u8) -> u8 {
@@ -291,12 +657,266 @@ impl Ppu {
 
     //TODO: Make colors changeable through config?
     fn color_index_to_color(color: u8) -> u32 {
-        match color {
-            0 => 0xFFE0F8D0,  //0xFFFFFFFF
-            1 => 0xFF88C070,  //0xFFAAAAAA
-            2 => 0xFF346856,  //0xFF555555
-            3 => 0xFF081820,  //0xFF000000
-            _ => 0xFFFFFFFF,
+        DMG_PALETTE.get(color as usize).copied().unwrap_or(0xFFFFFFFF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LCD, background and sprites on, SCX 0, no window
+    fn ppu(sprite_xs: &[u8]) -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = 0x83;
+        ppu.scx = 0;
+        ppu.line_sprites = sprite_xs.iter().map(|&x| Sprite { y: 16, x, tile: 0, flags: 0 }).collect();
+
+        ppu
+    }
+
+    #[test]
+    fn plain_line() {
+        assert_eq!(ppu(&[]).drawing_length(), 172);
+    }
+
+    #[test]
+    fn fine_scroll() {
+        let mut ppu = ppu(&[]);
+        ppu.scx = 0x0F;
+        assert_eq!(ppu.drawing_length(), 172 + 7);
+    }
+
+    #[test]
+    fn window() {
+        let mut ppu = ppu(&[]);
+        ppu.lcdc |= 0x20;
+        ppu.wx = 7;
+        assert_eq!(ppu.drawing_length(), 172);
+
+        // Only once WY matched
+        ppu.wy_triggered = true;
+        assert_eq!(ppu.drawing_length(), 172 + 6);
+    }
+
+    #[test]
+    fn sprite_at_x_0() {
+        assert_eq!(ppu(&[0]).drawing_length(), 172 + 11);
+    }
+
+    #[test]
+    fn sprites_on_the_same_tile() {
+        // Aligned to a tile: 5 dots waiting for the fetch plus 6 for the sprite
+        assert_eq!(ppu(&[8]).drawing_length(), 172 + 11);
+        // The second sprite on that tile only pays its own fetch
+        assert_eq!(ppu(&[8, 10]).drawing_length(), 172 + 11 + 6);
+        // 5 pixels into a tile the wait is over
+        assert_eq!(ppu(&[13]).drawing_length(), 172 + 6);
+    }
+
+    #[test]
+    fn hidden_sprites() {
+        assert_eq!(ppu(&[168]).drawing_length(), 172);
+
+        let mut ppu = ppu(&[8]);
+        ppu.lcdc &= !0x02;
+        assert_eq!(ppu.drawing_length(), 172);
+    }
+
+    /// LCD, background and window on with the window map at 0x9800 full of
+    /// tile 1 and the background map at 0x9C00 full of the blank tile 0.
+    /// Tile 1 has one colour per row and never colour 0.
+    fn window_ppu(wx: u8, wy: u8) -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = 0xB9;
+        ppu.bgp = 0xE4;
+        ppu.scx = 0;
+        ppu.scy = 0;
+        ppu.wx = wx;
+        ppu.wy = wy;
+
+        ppu.vram[0x1800..0x1C00].fill(1);
+        set_tile(&mut ppu, 1, [1, 2, 3, 1, 2, 3, 1, 2]);
+
+        ppu
+    }
+
+    fn set_tile(ppu: &mut Ppu, tile: usize, row_colors: [u8; 8]) {
+        for (row, color) in row_colors.into_iter().enumerate() {
+            ppu.vram[tile * 16 + row * 2] = if color & 1 != 0 { 0xFF } else { 0x00 };
+            ppu.vram[tile * 16 + row * 2 + 1] = if color & 2 != 0 { 0xFF } else { 0x00 };
+        }
+    }
+
+    /// Runs until LY changes, the colour indices then hold the line just drawn
+    fn run_line(ppu: &mut Ppu) {
+        let ly = ppu.scanline;
+        while ppu.scanline == ly {
+            ppu.step(4);
+        }
+    }
+
+    #[test]
+    fn window_line_only_advances_when_drawn() {
+        let mut ppu = window_ppu(7, 0);
+        for _ in 0..3 {
+            run_line(&mut ppu);
+        }
+        assert_eq!(ppu.window_line, 3);
+
+        // Pushed off the right edge and switched off, the window keeps its row
+        ppu.wx = 200;
+        run_line(&mut ppu);
+        ppu.wx = 7;
+        ppu.lcdc &= !0x20;
+        run_line(&mut ppu);
+        assert_eq!(ppu.window_line, 3);
+
+        // LY 5 shows window row 3
+        ppu.lcdc |= 0x20;
+        run_line(&mut ppu);
+        assert_eq!(ppu.window_line, 4);
+        assert_eq!(ppu.bg_color_index[0], 1);
+    }
+
+    #[test]
+    fn wy_is_latched_for_the_frame() {
+        let mut ppu = window_ppu(7, 2);
+        run_line(&mut ppu);
+        run_line(&mut ppu);
+        assert_eq!(ppu.bg_color_index[0], 0);
+
+        run_line(&mut ppu);
+        assert_eq!(ppu.bg_color_index[0], 1);
+
+        // Once LY matched WY, moving WY away no longer hides the window
+        ppu.wy = 100;
+        run_line(&mut ppu);
+        assert_eq!(ppu.bg_color_index[0], 2);
+        assert_eq!(ppu.window_line, 2);
+
+        // A WY that LY already passed never matches in this frame
+        let mut ppu = window_ppu(7, 50);
+        for _ in 0..5 {
+            run_line(&mut ppu);
         }
+        ppu.wy = 3;
+        while ppu.scanline != 0 {
+            run_line(&mut ppu);
+        }
+        assert_eq!(ppu.window_line, 0);
+
+        // but does in the next one
+        for _ in 0..4 {
+            run_line(&mut ppu);
+        }
+        assert_eq!(ppu.window_line, 1);
+    }
+
+    #[test]
+    fn wx_0_cuts_off_the_fine_scroll() {
+        // Tile 2 is all colour 3, tile 1 is colour 1 on row 0
+        let window_row = |wx: u8, scx: u8| {
+            let mut ppu = window_ppu(wx, 0);
+            ppu.scx = scx;
+            ppu.vram[0x1801] = 2;
+            set_tile(&mut ppu, 2, [3; 8]);
+            run_line(&mut ppu);
+
+            ppu.bg_color_index
+        };
+
+        let line = window_row(7, 0);
+        assert_eq!((line[7], line[8], line[15], line[16]), (1, 3, 3, 1));
+
+        // WX 0 drops the first 7 window pixels
+        let line = window_row(0, 0);
+        assert_eq!((line[0], line[1], line[8], line[9]), (1, 3, 3, 1));
+
+        // and the SCX fine scroll on top of that
+        let line = window_row(0, 3);
+        assert_eq!((line[0], line[5], line[6]), (3, 3, 1));
+        let line = window_row(0, 8);
+        assert_eq!((line[0], line[1], line[8], line[9]), (1, 3, 3, 1));
+    }
+
+    #[test]
+    fn wx_166_fills_the_next_line() {
+        let mut ppu = window_ppu(166, 0);
+        run_line(&mut ppu);
+        assert_eq!(ppu.bg_color_index[158], 0);
+        assert_eq!(ppu.bg_color_index[159], 1);
+        assert_eq!(ppu.window_line, 1);
+
+        // The whole next line shows window row 1
+        ppu.wx = 200;
+        run_line(&mut ppu);
+        assert!(ppu.bg_color_index.iter().all(|&color| color == 2));
+        assert_eq!(ppu.window_line, 2);
+
+        run_line(&mut ppu);
+        assert!(ppu.bg_color_index.iter().all(|&color| color == 0));
+    }
+
+    /// LCD, blank background and sprites on. Tiles 1-3 are solid in colours
+    /// 1-3, `sprites` go to OAM as (X, tile) on line 0.
+    fn sprite_ppu(sprites: &[(u8, u8)]) -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = 0x83;
+        ppu.bgp = 0xE4;
+        ppu.obp0 = 0xE4;
+        ppu.scx = 0;
+        ppu.scy = 0;
+        ppu.oam = [0; 0xA0];
+
+        for color in 1..=3 {
+            set_tile(&mut ppu, color as usize, [color; 8]);
+        }
+        for (entry, &(x, tile)) in ppu.oam.chunks_exact_mut(4).zip(sprites) {
+            entry.copy_from_slice(&[16, x, tile, 0]);
+        }
+
+        run_line(&mut ppu);
+        ppu
+    }
+
+    #[test]
+    fn sprites_off_screen_count_towards_the_limit() {
+        // 10 sprites left and right of the screen take all slots
+        let mut sprites = vec![(0, 1); 5];
+        sprites.extend([(168, 1); 5]);
+        sprites.push((8, 3));
+        let ppu = sprite_ppu(&sprites);
+        assert_eq!(ppu.line_sprites.len(), 10);
+        assert_eq!(ppu.framebuffer.pixels[0][0], DMG_PALETTE[0]);
+
+        sprites.remove(0);
+        let ppu = sprite_ppu(&sprites);
+        assert_eq!(ppu.framebuffer.pixels[0][0], DMG_PALETTE[3]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn overlapping_sprites_prefer_the_smaller_x() {
+        // The later OAM entry is further left and wins the overlap
+        let ppu = sprite_ppu(&[(12, 1), (8, 2)]);
+        let line = &ppu.framebuffer.pixels[0];
+        assert_eq!(line[4], DMG_PALETTE[2]);
+        assert_eq!(line[7], DMG_PALETTE[2]);
+        assert_eq!(line[8], DMG_PALETTE[1]);
+
+        let ppu = sprite_ppu(&[(8, 1), (12, 2)]);
+        let line = &ppu.framebuffer.pixels[0];
+        assert_eq!(line[4], DMG_PALETTE[1]);
+        assert_eq!(line[7], DMG_PALETTE[1]);
+        assert_eq!(line[8], DMG_PALETTE[2]);
+    }
+
+    #[test]
+    fn overlapping_sprites_with_the_same_x_go_by_oam_index() {
+        let ppu = sprite_ppu(&[(8, 1), (8, 2)]);
+        assert_eq!(ppu.framebuffer.pixels[0][0], DMG_PALETTE[1]);
+
+        let ppu = sprite_ppu(&[(8, 2), (8, 1)]);
+        assert_eq!(ppu.framebuffer.pixels[0][0], DMG_PALETTE[2]);
+    }
+}
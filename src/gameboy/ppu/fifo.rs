@@ -0,0 +1,466 @@
+use std::collections::VecDeque;
+
+use super::{Ppu, DMG_PALETTE};
+
+/// Dots the background fetcher needs for the tile number and both bit planes
+const FETCH_DOTS: u8 = 6;
+/// Dots the pixel output stalls while a sprite's row is fetched
+const SPRITE_FETCH_DOTS: u8 = 6;
+/// Upper bound for the dots needed to finish a line, in case mode 3 ends first
+const MAX_LINE_DOTS: u16 = 1000;
+
+/// A pixel waiting in the sprite FIFO
+#[derive(Clone, Copy, Default)]
+struct ObjPixel {
+    /// 0 is transparent
+    color_index: u8,
+    /// OAM attributes of the sprite it belongs to
+    flags: u8,
+}
+
+/// State of the pixel pipeline during mode 3 of one line
+pub(super) struct Fifo {
+    /// Colour indices fetched by the background/window fetcher
+    bg: VecDeque<u8>,
+    /// Sprite pixels lined up with the front of `bg`
+    obj: VecDeque<ObjPixel>,
+
+    /// Dots spent on the current fetch, it pushes once this reaches `FETCH_DOTS`
+    fetch_dots: u8,
+    /// Tile column the fetcher is on, counted from the start of the line or window
+    fetcher_x: u8,
+    tile: u8,
+    low: u8,
+    high: u8,
+
+    /// Pixels still to drop for the fine scroll (SCX % 8)
+    discard: u8,
+    /// Next screen column to draw
+    x: u8,
+    /// The fetcher switched to the window on this line
+    window: bool,
+    /// Dots the output waits for a sprite fetch
+    stall: u8,
+
+    /// Entries of `Ppu::line_sprites` whose row already went into `obj`
+    fetched: Vec<bool>,
+}
+
+impl Fifo {
+    pub(super) fn new() -> Self {
+        Self {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+
+            fetch_dots: 0,
+            fetcher_x: 0,
+            tile: 0,
+            low: 0,
+            high: 0,
+
+            discard: 0,
+            x: 160,
+            window: false,
+            stall: 0,
+
+            fetched: Vec::new(),
+        }
+    }
+}
+
+impl Ppu {
+    /// Resets the pipeline at the start of mode 3
+    pub(super) fn start_fifo_line(&mut self) {
+        let fifo = &mut self.fifo;
+
+        fifo.bg.clear();
+        fifo.obj.clear();
+        fifo.fetch_dots = 0;
+        fifo.fetcher_x = 0;
+        fifo.discard = self.scx & 0x07;
+        fifo.x = if self.scanline < 144 { 0 } else { 160 };
+        fifo.window = false;
+        fifo.stall = 0;
+        fifo.fetched.clear();
+        fifo.fetched.resize(self.line_sprites.len(), false);
+    }
+
+    pub(super) fn run_fifo(&mut self, dots: u16) {
+        for _ in 0..dots {
+            if self.fifo.x >= 160 {
+                return;
+            }
+            self.fifo_dot();
+        }
+    }
+
+    /// Draws whatever is left of the line when mode 3 ends
+    pub(super) fn finish_fifo_line(&mut self) {
+        self.run_fifo(MAX_LINE_DOTS);
+        self.fifo.x = 160;
+    }
+
+    fn fifo_dot(&mut self) {
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            return;
+        }
+
+        self.fetcher_dot();
+
+        if self.start_window() || self.fetch_sprite() {
+            return;
+        }
+
+        self.push_pixel();
+    }
+
+    /// One dot of the background/window fetcher. The tile number and each
+    /// bit plane are read 2 dots apart, so SCX, SCY, LCDC and VRAM changes
+    /// take effect in the middle of a line.
+    fn fetcher_dot(&mut self) {
+        let fifo = &self.fifo;
+        if fifo.fetch_dots == FETCH_DOTS {
+            // The row is ready, but only goes in once the FIFO ran empty
+            if fifo.bg.is_empty() {
+                self.push_row();
+            }
+            return;
+        }
+
+        self.fifo.fetch_dots += 1;
+        match self.fifo.fetch_dots {
+            2 => self.fifo.tile = self.read_vram(self.fetch_map_addr()),
+            4 => self.fifo.low = self.read_vram(self.fetch_tile_addr()),
+            6 => {
+                self.fifo.high = self.read_vram(self.fetch_tile_addr() + 1);
+                if self.fifo.bg.is_empty() {
+                    self.push_row();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn push_row(&mut self) {
+        let fifo = &mut self.fifo;
+
+        for bit in (0..8).rev() {
+            fifo.bg.push_back(((fifo.high >> bit) & 1) << 1 | ((fifo.low >> bit) & 1));
+        }
+
+        fifo.fetch_dots = 0;
+        fifo.fetcher_x = fifo.fetcher_x.wrapping_add(1);
+    }
+
+    /// Address of the tile number in the background or window tile map
+    fn fetch_map_addr(&self) -> u16 {
+        let (map_base, x, y) = if self.fifo.window {
+            let map_base = if self.lcdc & 0x40 != 0 { 0x9C00 } else { 0x9800 };
+            (map_base, self.fifo.fetcher_x as u16 & 0x1F, self.window_line as u16)
+        } else {
+            let map_base = if self.lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
+            let x = ((self.scx >> 3) as u16 + self.fifo.fetcher_x as u16) & 0x1F;
+            (map_base, x, self.scanline.wrapping_add(self.scy) as u16)
+        };
+
+        map_base + (y / 8) * 32 + x
+    }
+
+    /// Address of the low bit plane of the fetched tile's current row
+    fn fetch_tile_addr(&self) -> u16 {
+        let y = if self.fifo.window {
+            self.window_line
+        } else {
+            self.scanline.wrapping_add(self.scy)
+        } as u16;
+
+        let tile = self.fifo.tile;
+        let tile_addr = if self.lcdc & 0x10 != 0 {
+            0x8000 + tile as u16 * 16
+        } else {
+            0x9000_u16.wrapping_add(((tile as i8) as i16 * 16) as u16)
+        };
+
+        tile_addr + (y % 8) * 2
+    }
+
+    /// Switches the fetcher over to the window once the output reaches WX
+    fn start_window(&mut self) -> bool {
+        let Some(window) = self.window_start() else {
+            return false;
+        };
+
+        let fifo = &mut self.fifo;
+        if fifo.window || fifo.x < window.x || fifo.discard > 0 {
+            return false;
+        }
+
+        self.window_drawn = true;
+        fifo.window = true;
+        // Window pixels left of the screen edge go the same way as the fine scroll
+        fifo.discard = window.skip;
+        fifo.bg.clear();
+        fifo.fetch_dots = 0;
+        fifo.fetcher_x = 0;
+
+        true
+    }
+
+    /// Starts fetching the next sprite that begins at the current column.
+    /// The output waits meanwhile.
+    fn fetch_sprite(&mut self) -> bool {
+        if self.lcdc & 0x02 == 0 || self.fifo.discard > 0 || self.fifo.bg.is_empty() {
+            return false;
+        }
+
+        let x = self.fifo.x as i16;
+        // Of several sprites starting here the one with the smallest X goes
+        // first and so wins, ties go to the one first in OAM
+        let Some(index) = (0..self.line_sprites.len())
+            .filter(|&i| !self.fifo.fetched[i] && self.line_sprites[i].x as i16 <= x + 8)
+            .min_by_key(|&i| self.line_sprites[i].x) else {
+            return false;
+        };
+
+        let sprite = self.line_sprites[index];
+        let (byte1, byte2) = self.sprite_row(&sprite);
+        let fifo = &mut self.fifo;
+
+        fifo.fetched[index] = true;
+        fifo.stall = SPRITE_FETCH_DOTS - 1;
+
+        if fifo.obj.len() < 8 {
+            fifo.obj.resize(8, ObjPixel::default());
+        }
+
+        for px in 0..8u8 {
+            // Pixels left of the screen edge are cut off
+            let offset = sprite.x as i16 - 8 + px as i16 - x;
+            if offset < 0 {
+                continue;
+            }
+
+            // Earlier sprites keep their pixels, only transparent slots are taken
+            let slot = &mut fifo.obj[offset as usize];
+            if slot.color_index == 0 {
+                *slot = ObjPixel {
+                    color_index: Self::sprite_color_index(byte1, byte2, sprite.flags, px),
+                    flags: sprite.flags,
+                };
+            }
+        }
+
+        true
+    }
+
+    /// Shifts one pixel out of both FIFOs, mixes them with the palettes
+    /// current at this dot and draws it
+    fn push_pixel(&mut self) {
+        let Some(bg_index) = self.fifo.bg.pop_front() else {
+            return;
+        };
+
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+
+        let obj = self.fifo.obj.pop_front().unwrap_or_default();
+
+        // On the DMG, LCDC bit 0 blanks background and window alike to
+        // white, no matter what BGP says
+        let bg_enabled = self.lcdc & 0x01 != 0;
+        let bg_index = if bg_enabled { bg_index } else { 0 };
+
+        let obj_visible = obj.color_index != 0
+            && self.lcdc & 0x02 != 0
+            && (obj.flags & 0x80 == 0 || bg_index == 0);
+
+        let color = if obj_visible {
+            let obp = if obj.flags & 0x10 != 0 { self.obp1 } else { self.obp0 };
+            Self::color_index_to_color(Self::apply_palette(obj.color_index, obp))
+        } else if bg_enabled {
+            Self::color_index_to_color(Self::apply_palette(bg_index, self.bgp))
+        } else {
+            DMG_PALETTE[0]
+        };
+
+        let x = self.fifo.x as usize;
+        self.bg_color_index[x] = bg_index;
+        self.framebuffer.pixels[self.scanline as usize][x] = color;
+        self.fifo.x += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Framebuffer, PpuMode, Renderer};
+    use crate::gameboy::screen::framebuffer::SCREEN_H;
+
+    /// Fills VRAM with a fixed pseudo random pattern, so every tile and map
+    /// entry differs
+    fn fill_vram(ppu: &mut Ppu) {
+        let mut state = 0x2545_F491_u32;
+        for byte in ppu.vram.iter_mut() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *byte = state as u8;
+        }
+    }
+
+    /// Places sprite `index` in OAM
+    fn sprite(ppu: &mut Ppu, index: usize, y: u8, x: u8, tile: u8, flags: u8) {
+        ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[y, x, tile, flags]);
+    }
+
+    /// Runs the first frame, stepping `dots` at a time, and returns its picture
+    fn frame(renderer: Renderer, dots: u16, setup: &impl Fn(&mut Ppu)) -> Framebuffer {
+        let mut ppu = Ppu::new();
+        ppu.power_on();
+        fill_vram(&mut ppu);
+        ppu.bgp = 0xE4;
+        ppu.obp0 = 0xD2;
+        ppu.obp1 = 0x1B;
+        setup(&mut ppu);
+        ppu.set_renderer(renderer);
+
+        while !ppu.step(dots).frame_ready {}
+
+        ppu.framebuffer
+    }
+
+    /// The FIFO has to draw the same picture as the scanline renderer, no
+    /// matter how the dots are handed to it
+    fn assert_same_picture(setup: impl Fn(&mut Ppu)) {
+        let expected = frame(Renderer::Scanline, 4, &setup);
+
+        for dots in [1, 3, 4, 80] {
+            let actual = frame(Renderer::Fifo, dots, &setup);
+            for y in 0..SCREEN_H {
+                assert_eq!(actual.pixels[y], expected.pixels[y], "line {} with {} dots per step", y, dots);
+            }
+        }
+    }
+
+    #[test]
+    fn background() {
+        assert_same_picture(|ppu| {
+            ppu.lcdc = 0x91;
+            ppu.scy = 5;
+        });
+    }
+
+    #[test]
+    fn background_signed_tile_data_and_upper_map() {
+        assert_same_picture(|ppu| {
+            ppu.lcdc = 0x89;
+            ppu.scx = 0xF0;
+            ppu.scy = 0xF9;
+        });
+    }
+
+    #[test]
+    fn fine_scroll_is_discarded() {
+        for scx in 1..8 {
+            assert_same_picture(|ppu| {
+                ppu.lcdc = 0x91;
+                ppu.scx = scx;
+            });
+        }
+    }
+
+    #[test]
+    fn window_restarts_the_fetcher() {
+        for wx in [7, 20, 83, 166] {
+            assert_same_picture(|ppu| {
+                ppu.lcdc = 0xF1;
+                ppu.scx = 5;
+                ppu.wy = 30;
+                ppu.wx = wx;
+            });
+        }
+    }
+
+    #[test]
+    fn window_left_of_the_screen_edge() {
+        for wx in 0..7 {
+            assert_same_picture(|ppu| {
+                ppu.lcdc = 0xB1;
+                ppu.scx = 3;
+                ppu.wy = 10;
+                ppu.wx = wx;
+            });
+        }
+    }
+
+    #[test]
+    fn sprites() {
+        assert_same_picture(|ppu| {
+            ppu.lcdc = 0x93;
+            ppu.scx = 2;
+            // Partly off the left edge, overlapping, flipped, on OBP1 and behind the background
+            let sprites = [
+                (16, 0, 0x10, 0x00), (16, 4, 0x11, 0x20), (20, 8, 0x12, 0x10),
+                (22, 10, 0x13, 0x80), (24, 13, 0x14, 0x40), (24, 13, 0x15, 0x00),
+                (30, 100, 0x16, 0x60), (31, 104, 0x17, 0x90), (60, 160, 0x18, 0x00),
+                (60, 167, 0x19, 0x10), (90, 50, 0x1A, 0x80), (90, 52, 0x1B, 0x00),
+            ];
+            for (i, &(y, x, tile, flags)) in sprites.iter().enumerate() {
+                sprite(ppu, i, y, x, tile, flags);
+            }
+        });
+    }
+
+    #[test]
+    fn tall_sprites_with_window() {
+        assert_same_picture(|ppu| {
+            ppu.lcdc = 0xF7;
+            ppu.wy = 40;
+            ppu.wx = 60;
+            for i in 0..40 {
+                sprite(ppu, i, (16 + i * 3) as u8, (8 + i * 7) as u8, (i * 5) as u8, ((i & 0x07) << 4) as u8);
+            }
+        });
+    }
+
+    #[test]
+    fn disabled_background_is_white() {
+        let setup = |ppu: &mut Ppu| {
+            ppu.lcdc = 0xF2;
+            // Color 0 of BGP would be black
+            ppu.bgp = 0x1B;
+            ppu.wy = 0;
+            ppu.wx = 7;
+            sprite(ppu, 0, 16, 8, 0x20, 0x00);
+        };
+        assert_same_picture(setup);
+
+        let picture = frame(Renderer::Fifo, 4, &setup);
+        assert_eq!(picture.pixels[100], [DMG_PALETTE[0]; 160]);
+    }
+
+    #[test]
+    fn fifo_finishes_within_the_mode_3_length() {
+        let mut ppu = Ppu::new();
+        ppu.power_on();
+        fill_vram(&mut ppu);
+        ppu.lcdc = 0xF3;
+        ppu.scx = 5;
+        ppu.wy = 50;
+        ppu.wx = 90;
+        for i in 0..40 {
+            sprite(&mut ppu, i, (16 + i * 2) as u8, (i * 9) as u8, 0, 0);
+        }
+        ppu.set_renderer(Renderer::Fifo);
+
+        while !ppu.step(1).frame_ready {
+            // The line is complete without the catch up in `finish_fifo_line`
+            if ppu.mode == PpuMode::Drawing && ppu.dot_counter + 1 == ppu.drawing_dots {
+                assert_eq!(ppu.fifo.x, 160, "line {}", ppu.scanline);
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+/// Everything that happens at a known point in time without the CPU
+/// touching a register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+    /// The PPU finishes its current mode
+    PpuMode = 0,
+    /// TIMA overflows to 0
+    TimerOverflow = 1,
+    /// TMA is copied to TIMA one M-cycle after an overflow
+    TimerReload = 2,
+    /// Falling edge of DIV bit 4, which steps the APU frame sequencer
+    FrameSequencer = 3,
+    /// The serial port shifts out (and in) the next bit
+    SerialBit = 4,
+    /// The OAM DMA transfer has copied its last byte
+    DmaDone = 5,
+}
+
+const EVENT_COUNT: usize = 6;
+
+impl EventKind {
+    const ALL: [EventKind; EVENT_COUNT] = [
+        EventKind::PpuMode,
+        EventKind::TimerOverflow,
+        EventKind::TimerReload,
+        EventKind::FrameSequencer,
+        EventKind::SerialBit,
+        EventKind::DmaDone,
+    ];
+}
+
+/// Keeps the current time in T-cycles and when each kind of event is due
+/// next. There is at most one pending event of each kind, so rescheduling
+/// simply replaces it.
+pub struct Scheduler {
+    now: u64,
+    /// Time of the earliest pending event, so `pop_due` is cheap when
+    /// nothing happens
+    next: u64,
+    events: [Option<u64>; EVENT_COUNT],
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            now: 0,
+            next: u64::MAX,
+            events: [None; EVENT_COUNT],
+        }
+    }
+
+    /// T-cycles since power on
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn advance(&mut self, cycles: u64) {
+        self.now += cycles;
+    }
+
+    /// Schedules `kind` at the absolute time `at`, replacing a pending one
+    pub fn schedule(&mut self, kind: EventKind, at: u64) {
+        self.events[kind as usize] = Some(at);
+        self.next = self.next.min(at);
+    }
+
+    pub fn cancel(&mut self, kind: EventKind) {
+        if self.events[kind as usize].take().is_some() {
+            self.update_next();
+        }
+    }
+
+    pub fn is_scheduled(&self, kind: EventKind) -> bool {
+        self.events[kind as usize].is_some()
+    }
+
+    /// Removes the earliest event that is due by now
+    ///
+    /// * `result` - Returns the event and the time it was scheduled for
+    pub fn pop_due(&mut self) -> Option<(EventKind, u64)> {
+        if self.next > self.now {
+            return None;
+        }
+
+        let (kind, at) = EventKind::ALL.into_iter()
+            .filter_map(|kind| self.events[kind as usize].map(|at| (kind, at)))
+            .min_by_key(|&(_, at)| at)?;
+
+        // The earliest event was moved to a later time
+        if at > self.now {
+            self.next = at;
+            return None;
+        }
+
+        self.events[kind as usize] = None;
+        self.update_next();
+
+        Some((kind, at))
+    }
+
+    /// Cycles until the next pending event, if there is one
+    pub fn cycles_until_next(&self) -> Option<u64> {
+        (self.next != u64::MAX).then(|| self.next.saturating_sub(self.now))
+    }
+
+    fn update_next(&mut self) {
+        self.next = self.events.iter().flatten().copied().min().unwrap_or(u64::MAX);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_come_in_time_order() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(EventKind::SerialBit, 30);
+        scheduler.schedule(EventKind::PpuMode, 10);
+        scheduler.schedule(EventKind::TimerOverflow, 20);
+        assert_eq!(scheduler.pop_due(), None);
+        assert_eq!(scheduler.cycles_until_next(), Some(10));
+
+        scheduler.advance(25);
+        assert_eq!(scheduler.pop_due(), Some((EventKind::PpuMode, 10)));
+        assert_eq!(scheduler.pop_due(), Some((EventKind::TimerOverflow, 20)));
+        assert_eq!(scheduler.pop_due(), None);
+        assert_eq!(scheduler.cycles_until_next(), Some(5));
+
+        scheduler.advance(5);
+        assert_eq!(scheduler.pop_due(), Some((EventKind::SerialBit, 30)));
+        assert_eq!(scheduler.cycles_until_next(), None);
+    }
+
+    #[test]
+    fn rescheduling_replaces_the_event() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(EventKind::PpuMode, 10);
+        scheduler.schedule(EventKind::PpuMode, 50);
+
+        scheduler.advance(20);
+        assert_eq!(scheduler.pop_due(), None);
+        assert_eq!(scheduler.cycles_until_next(), Some(30));
+
+        scheduler.schedule(EventKind::PpuMode, 15);
+        assert_eq!(scheduler.pop_due(), Some((EventKind::PpuMode, 15)));
+        assert!(!scheduler.is_scheduled(EventKind::PpuMode));
+    }
+
+    #[test]
+    fn cancelled_events_never_come() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(EventKind::TimerReload, 4);
+        scheduler.schedule(EventKind::DmaDone, 8);
+
+        scheduler.cancel(EventKind::TimerReload);
+        assert_eq!(scheduler.cycles_until_next(), Some(8));
+
+        scheduler.advance(8);
+        assert_eq!(scheduler.pop_due(), Some((EventKind::DmaDone, 8)));
+        assert_eq!(scheduler.pop_due(), None);
+
+        scheduler.cancel(EventKind::DmaDone);
+        assert_eq!(scheduler.cycles_until_next(), None);
+    }
+}
@@ -1,2 +1,2 @@
 pub mod framebuffer;
-pub mod window;
\ No newline at end of file
+pub mod image;
\ No newline at end of file
@@ -3,11 +3,22 @@ pub const SCREEN_H: usize = 144;
 
 pub type Color = u32;
 
+/// The four shades the PPU draws with, lightest first
+pub const DMG_PALETTE: [Color; 4] = [0xFFE0F8D0, 0xFF88C070, 0xFF346856, 0xFF081820];
+/// The same shades as plain greys, as used by reference screenshots like dmg-acid2's
+pub const GREYSCALE_PALETTE: [Color; 4] = [0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555, 0xFF000000];
+
 #[derive(Clone)]
 pub struct Framebuffer {
     pub pixels: [[Color; SCREEN_W]; SCREEN_H],
 }
 
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Framebuffer {
     pub fn new() -> Self {
         Self {
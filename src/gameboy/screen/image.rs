@@ -0,0 +1,113 @@
+use std::{fs::File, io::{self, BufReader, BufWriter}, path::Path};
+
+use super::framebuffer::{Color, Framebuffer, DMG_PALETTE, GREYSCALE_PALETTE, SCREEN_H, SCREEN_W};
+
+/// Colour of mismatching pixels in a diff image
+const DIFF_COLOR: Color = 0xFFFF0000;
+
+/// Pixels in which two pictures differ
+pub struct ImageDiff {
+    /// Number of differing pixels
+    pub pixels: usize,
+    /// The expected picture faded out, with every differing pixel in red
+    pub image: Framebuffer,
+}
+
+/// Writes the framebuffer as 8-bit RGB PNG file to `path`
+pub fn save_png(framebuffer: &Framebuffer, path: &Path) -> Result<(), io::Error> {
+    let file = File::create(path)?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), SCREEN_W as u32, SCREEN_H as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut data = Vec::with_capacity(SCREEN_W * SCREEN_H * 3);
+    for color in framebuffer.as_flat_buffer() {
+        data.push((color >> 16) as u8);
+        data.push((color >> 8) as u8);
+        data.push(color as u8);
+    }
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&data).map_err(io::Error::other)?;
+
+    Ok(())
+}
+
+/// Reads a 160x144 PNG file, e.g. a reference screenshot
+///
+/// * `result` - Fails if the file is no PNG or has the wrong size
+pub fn load_png(path: &Path) -> Result<Framebuffer, io::Error> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    // Palette and low bit depth images come out as plain 8-bit samples
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let mut reader = decoder.read_info().map_err(io::Error::other)?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(io::Error::other)?;
+
+    if info.width as usize != SCREEN_W || info.height as usize != SCREEN_H {
+        return Err(io::Error::other(format!("expected {}x{} pixels, got {}x{}", SCREEN_W, SCREEN_H, info.width, info.height)));
+    }
+
+    let channels = info.color_type.samples();
+    let mut framebuffer = Framebuffer::new();
+
+    for (i, pixel) in data[..info.buffer_size()].chunks_exact(channels).enumerate() {
+        let (r, g, b) = match info.color_type {
+            png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => (pixel[0], pixel[0], pixel[0]),
+            _ => (pixel[0], pixel[1], pixel[2]),
+        };
+
+        framebuffer.pixels[i / SCREEN_W][i % SCREEN_W] = 0xFF000000 | (r as u32) << 16 | (g as u32) << 8 | b as u32;
+    }
+
+    Ok(framebuffer)
+}
+
+/// Shade from 0 (lightest) to 3 (darkest). The PPU's own colours map
+/// exactly, anything else by its brightness as a grey.
+fn shade(color: Color) -> u8 {
+    if let Some(index) = DMG_PALETTE.iter().position(|&dmg| dmg == color) {
+        return index as u8;
+    }
+
+    let brightness = (((color >> 16) & 0xFF) + ((color >> 8) & 0xFF) + (color & 0xFF)) / 3;
+    3 - ((brightness + 42) / 85) as u8
+}
+
+/// The picture in the greyscale palette reference screenshots use
+pub fn to_greyscale(framebuffer: &Framebuffer) -> Framebuffer {
+    let mut grey = framebuffer.clone();
+    for pixel in grey.pixels.iter_mut().flatten() {
+        *pixel = GREYSCALE_PALETTE[shade(*pixel) as usize];
+    }
+
+    grey
+}
+
+/// Compares two pictures shade by shade, so a screenshot taken in the green
+/// DMG palette matches a greyscale reference
+///
+/// * `result` - Returns `None` if both show the same picture
+pub fn compare(actual: &Framebuffer, expected: &Framebuffer) -> Option<ImageDiff> {
+    let mut image = Framebuffer::new();
+    let mut pixels = 0;
+
+    for y in 0..SCREEN_H {
+        for x in 0..SCREEN_W {
+            let expected_shade = shade(expected.pixels[y][x]);
+
+            image.pixels[y][x] = if shade(actual.pixels[y][x]) != expected_shade {
+                pixels += 1;
+                DIFF_COLOR
+            } else {
+                // Fade towards white, so the red pixels stand out
+                let grey = 0xFF - expected_shade as u32 * 0x20;
+                0xFF000000 | grey << 16 | grey << 8 | grey
+            };
+        }
+    }
+
+    (pixels > 0).then_some(ImageDiff { pixels, image })
+}
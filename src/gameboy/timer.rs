@@ -1,83 +1,301 @@
+/// DIV, TIMA, TMA and TAC
+///
+/// Nothing is counted cycle by cycle. DIV is the upper byte of a 16 bit
+/// counter running since `synced_at`, and TIMA increments on every falling
+/// edge of the counter bit TAC selects, so both follow from the elapsed
+/// time. The MMU schedules the overflows.
 pub struct Timer {
-    internal_counter: u16,
-    last_tac: u8,
+    /// Internal counter at `synced_at`
+    counter: u16,
+    synced_at: u64,
+
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// TIMA overflowed and reads 0 until TMA is copied in one M-cycle later
+    reload_pending: bool,
 }
 
+/// Side effects of a timer register write
 pub struct TimerUpdate {
-    pub new_div: Option<u8>,
-    pub new_tima: Option<u8>,
-    pub timer_interrupt: bool,
+    /// TIMA overflowed, so the reload has to be scheduled
+    pub overflow: bool,
+    /// DIV bit 4 fell, which steps the APU frame sequencer
+    pub frame_sequencer_clock: bool,
 }
 
-pub enum TimerAddr {
-    //DIV = 0xFF04,
-    TIMA = 0xFF05,
-    TMA = 0xFF06,
-    TAC = 0xFF07
+/// The counter bit whose falling edge steps the APU frame sequencer
+const FRAME_SEQUENCER_BIT: u16 = 12;
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Timer {
     pub fn new() -> Self {
+        Self::with_counter(0)
+    }
+
+    /// Timer whose internal counter (DIV is its upper byte) starts at `internal_counter`
+    pub fn with_counter(internal_counter: u16) -> Self {
         Self {
-            internal_counter: 0,
-            last_tac: 0,
+            counter: internal_counter,
+            synced_at: 0,
+
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload_pending: false,
         }
     }
 
-    pub fn update(&mut self, cycles: u8, tac: u8, tima: u8, tma: u8) -> TimerUpdate {
-        let mut result = TimerUpdate {
-            new_div: None,
-            new_tima: None,
-            timer_interrupt: false,
-        };
-        
-        let timer_enabled = tac & 0x04 != 0;
-        let old_timer_enabled = self.last_tac & 0x04 != 0;
+    pub fn read(&self, addr: u16, now: u64) -> u8 {
+        match addr {
+            0xFF04 => (self.counter_at(now) >> 8) as u8,
+            // The overflow is an event, so TIMA never wraps in between syncs
+            0xFF05 => self.tima.wrapping_add(self.edges_until(now) as u8),
+            0xFF06 => self.tma,
+            0xFF07 => self.tac,
+            _ => 0xFF,
+        }
+    }
 
-        let bit_position = match tac & 0x03 {
-            0 => 9, 1 => 3, 2 => 5, 3 => 7, _ => unreachable!(),
+    pub fn write(&mut self, addr: u16, value: u8, now: u64) -> TimerUpdate {
+        let mut update = TimerUpdate {
+            overflow: self.sync(now),
+            frame_sequencer_clock: false,
         };
 
-        if old_timer_enabled && !timer_enabled {
-            if (self.internal_counter >> bit_position) & 0x01 == 1 {
-                if tima == 0xFF {
-                    result.new_tima = Some(tma);
-                    result.timer_interrupt = true;
-                } else {
-                    result.new_tima = Some(tima + 1)
+        match addr {
+            0xFF04 => {
+                // Resetting the counter is a falling edge for every bit that was set
+                if self.selected_bit_set() {
+                    update.overflow |= self.increment();
                 }
-            }
+                update.frame_sequencer_clock = self.counter & (1 << FRAME_SEQUENCER_BIT) != 0;
+
+                self.counter = 0;
+            },
+            0xFF05 => {
+                // Writing TIMA in the M-cycle after an overflow cancels the reload
+                self.reload_pending = false;
+                self.tima = value;
+            },
+            0xFF06 => self.tma = value,
+            0xFF07 => {
+                // TIMA is clocked by the selected bit ANDed with the enable
+                // bit, so switching either can produce a falling edge
+                let was_set = self.selected_bit_set();
+                self.tac = value;
+
+                if was_set && !self.selected_bit_set() {
+                    update.overflow |= self.increment();
+                }
+            },
+            _ => {},
+        }
+
+        update
+    }
+
+    /// Catches up to `now`. Has to be called exactly at an overflow.
+    ///
+    /// * `result` - Returns true if TIMA overflowed
+    pub fn sync(&mut self, now: u64) -> bool {
+        let edges = self.edges_until(now);
+
+        self.counter = self.counter_at(now);
+        self.synced_at = now;
+
+        let tima = self.tima as u64 + edges;
+        if tima > 0xFF {
+            self.tima = 0;
+            self.reload_pending = true;
+            return true;
+        }
+
+        self.tima = tima as u8;
+        false
+    }
+
+    /// Copies TMA to TIMA one M-cycle after an overflow
+    ///
+    /// * `result` - Returns false if the reload was cancelled by a TIMA write
+    pub fn reload(&mut self, now: u64) -> bool {
+        self.sync(now);
+
+        if !std::mem::take(&mut self.reload_pending) {
+            return false;
+        }
+
+        self.tima = self.tma;
+        true
+    }
+
+    pub fn reload_pending(&self) -> bool {
+        self.reload_pending
+    }
+
+    /// When TIMA overflows next if nothing is written in between
+    pub fn next_overflow(&self, now: u64) -> Option<u64> {
+        let period = self.period()?;
+        if self.reload_pending {
+            return None;
+        }
+
+        let tima = self.tima as u64 + self.edges_until(now);
+        let counter = self.counter_at(now) as u64;
+        let first_edge = period - counter % period;
+
+        Some(now + first_edge + (0xFF - tima) * period)
+    }
+
+    /// When DIV bit 4 falls next, which steps the APU frame sequencer
+    pub fn next_frame_sequencer_clock(&self, now: u64) -> u64 {
+        let period = 1 << (FRAME_SEQUENCER_BIT + 1);
+
+        now + period - self.counter_at(now) as u64 % period
+    }
+
+    fn counter_at(&self, now: u64) -> u16 {
+        self.counter.wrapping_add((now - self.synced_at) as u16)
+    }
+
+    /// Cycles between two TIMA increments, None while the timer is stopped
+    fn period(&self) -> Option<u64> {
+        if self.tac & 0x04 == 0 {
+            return None;
         }
-        
-        for _ in 0..cycles {
-            let old_counter = self.internal_counter;
-            self.internal_counter = self.internal_counter.wrapping_add(1);
-            
-            if (self.internal_counter >> 8) != (old_counter >> 8) {
-                result.new_div = Some((self.internal_counter >> 8) as u8);
-            }
-            
-            if !timer_enabled { continue; }
-
-            let old_bit = (old_counter >> bit_position) & 0x01;
-            let new_bit = (self.internal_counter >> bit_position) & 0x01;
-            
-            if old_bit != 1 || new_bit != 0 { continue; }
-            
-            let current_tima = result.new_tima.unwrap_or(tima);
-            if current_tima == 0xFF {
-                result.new_tima = Some(tma);
-                result.timer_interrupt = true;
-            } else {
-                result.new_tima = Some(current_tima + 1);
-            }
+
+        Some(match self.tac & 0x03 {
+            0 => 1024,
+            1 => 16,
+            2 => 64,
+            _ => 256,
+        })
+    }
+
+    /// Falling edges of the selected counter bit since the last sync. The bit
+    /// falls whenever the counter becomes a multiple of the period.
+    fn edges_until(&self, now: u64) -> u64 {
+        let Some(period) = self.period() else {
+            return 0;
+        };
+
+        let start = self.counter as u64;
+        let end = start + (now - self.synced_at);
+
+        end / period - start / period
+    }
+
+    fn selected_bit_set(&self) -> bool {
+        self.period().is_some_and(|period| self.counter as u64 & (period / 2) != 0)
+    }
+
+    /// A single TIMA increment caused by a register write
+    fn increment(&mut self) -> bool {
+        if self.tima == 0xFF {
+            self.tima = 0;
+            self.reload_pending = true;
+            return true;
         }
-        
-        self.last_tac = tac;
-        result
+
+        self.tima += 1;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Timer counting every 16 cycles (counter bit 3) from `counter`
+    fn running(counter: u16) -> Timer {
+        let mut timer = Timer::with_counter(counter);
+        timer.write(0xFF07, 0x05, 0);
+        timer
     }
 
-    pub fn reset_divider(&mut self) {
-        self.internal_counter = 0;
+    #[test]
+    fn counts_falling_edges() {
+        let timer = running(0);
+        assert_eq!(timer.read(0xFF05, 15), 0);
+        assert_eq!(timer.read(0xFF05, 16), 1);
+        assert_eq!(timer.read(0xFF05, 16 * 10 + 3), 10);
+        assert_eq!(timer.read(0xFF04, 0x1234), 0x12);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn overflow_reloads_one_m_cycle_later() {
+        let mut timer = running(0);
+        timer.write(0xFF06, 0x42, 0);
+        timer.write(0xFF05, 0xFF, 0);
+        assert_eq!(timer.next_overflow(0), Some(16));
+
+        assert!(timer.sync(16));
+        assert_eq!(timer.read(0xFF05, 16), 0x00);
+        assert!(timer.reload_pending());
+
+        assert!(timer.reload(20));
+        assert_eq!(timer.read(0xFF05, 20), 0x42);
+    }
+
+    #[test]
+    fn tima_write_cancels_the_reload() {
+        let mut timer = running(0);
+        timer.write(0xFF06, 0x42, 0);
+        timer.write(0xFF05, 0xFF, 0);
+
+        assert!(timer.sync(16));
+        timer.write(0xFF05, 0x10, 18);
+        assert!(!timer.reload(20));
+        assert_eq!(timer.read(0xFF05, 20), 0x10);
+    }
+
+    #[test]
+    fn div_write_can_be_a_falling_edge() {
+        // Bit 3 set: resetting the counter drops it
+        let mut timer = running(0x08);
+        timer.write(0xFF04, 0x00, 0);
+        assert_eq!(timer.read(0xFF05, 0), 1);
+        assert_eq!(timer.read(0xFF04, 0), 0);
+
+        // Bit 3 clear: nothing happens
+        let mut timer = running(0x17);
+        timer.write(0xFF04, 0x00, 0);
+        assert_eq!(timer.read(0xFF05, 0), 0);
+
+        // The edge can overflow TIMA too
+        let mut timer = running(0x08);
+        timer.write(0xFF05, 0xFF, 0);
+        assert!(timer.write(0xFF04, 0x00, 0).overflow);
+        assert!(timer.reload_pending());
+    }
+
+    #[test]
+    fn div_write_clocks_the_frame_sequencer() {
+        let mut timer = Timer::with_counter(1 << FRAME_SEQUENCER_BIT);
+        assert!(timer.write(0xFF04, 0x00, 0).frame_sequencer_clock);
+        assert!(!timer.write(0xFF04, 0x00, 4).frame_sequencer_clock);
+    }
+
+    #[test]
+    fn tac_write_can_be_a_falling_edge() {
+        // Selecting counter bit 9, which is clear
+        let mut timer = running(0x08);
+        timer.write(0xFF07, 0x04, 0);
+        assert_eq!(timer.read(0xFF05, 0), 1);
+
+        // Stopping the timer
+        let mut timer = running(0x08);
+        timer.write(0xFF07, 0x01, 0);
+        assert_eq!(timer.read(0xFF05, 0), 1);
+
+        // Bit 3 stays selected
+        let mut timer = running(0x08);
+        timer.write(0xFF07, 0x05, 0);
+        assert_eq!(timer.read(0xFF05, 0), 0);
+    }
+}
@@ -0,0 +1,112 @@
+use std::{collections::VecDeque, io::{self, Write}, ops::RangeInclusive};
+
+use super::{cpu::{registers::Reg8, Cpu}, mmu::Mmu};
+
+/// Which instructions end up in the trace
+#[derive(Clone, Debug, Default)]
+pub struct TraceOptions {
+    /// Only instructions whose address lies in this range
+    pub pc_range: Option<RangeInclusive<u16>>,
+    /// Only instructions executed from this ROM bank
+    pub rom_bank: Option<usize>,
+    /// Keep only the last n lines in memory and write them when the trace
+    /// is dropped, which also happens while unwinding from a panic
+    pub last: Option<usize>,
+    /// LY keeps following the PPU instead of reading 0x90. Games usually
+    /// need this, comparing against Gameboy Doctor logs does not work with it.
+    pub keep_ly: bool,
+}
+
+/// Writes one line per executed instruction in the Gameboy Doctor format:
+///
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+///
+/// The state is logged before the instruction runs. Gameboy Doctor expects
+/// LY to always read 0x90, which `GameBoy::set_tracer` takes care of unless
+/// `TraceOptions::keep_ly` is set.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    options: TraceOptions,
+    ring: VecDeque<String>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, options: TraceOptions) -> Self {
+        Self {
+            out,
+            options,
+            ring: VecDeque::new(),
+        }
+    }
+
+    pub fn options(&self) -> &TraceOptions {
+        &self.options
+    }
+
+    /// Logs the instruction the CPU executes next, if the filters let it through.
+    /// Interrupt dispatches and halted steps execute nothing and are skipped.
+    pub fn trace(&mut self, cpu: &Cpu, mmu: &Mmu) -> io::Result<()> {
+        let pc = cpu.program_counter;
+
+        let pending = mmu.peek8(0xFFFF) & mmu.peek8(0xFF0F) & 0x1F;
+        if cpu.halted || cpu.stopped || (cpu.ime && pending != 0) {
+            return Ok(());
+        }
+
+        if self.options.pc_range.as_ref().is_some_and(|range| !range.contains(&pc)) {
+            return Ok(());
+        }
+
+        if let Some(bank) = self.options.rom_bank && mmu.rom_bank(pc) != Some(bank) {
+            return Ok(());
+        }
+
+        let line = Self::format(cpu, mmu);
+
+        match self.options.last {
+            Some(last) => {
+                if self.ring.len() == last {
+                    self.ring.pop_front();
+                }
+                if last > 0 {
+                    self.ring.push_back(line);
+                }
+                Ok(())
+            },
+            None => writeln!(self.out, "{}", line),
+        }
+    }
+
+    /// Writes the lines kept for `TraceOptions::last` and flushes the output
+    pub fn dump(&mut self) -> io::Result<()> {
+        for line in self.ring.drain(..) {
+            writeln!(self.out, "{}", line)?;
+        }
+
+        self.out.flush()
+    }
+
+    fn format(cpu: &Cpu, mmu: &Mmu) -> String {
+        let registers = &cpu.registers;
+        let pc = cpu.program_counter;
+
+        format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            registers.read8(&Reg8::A), registers.flag_register.flags,
+            registers.read8(&Reg8::B), registers.read8(&Reg8::C),
+            registers.read8(&Reg8::D), registers.read8(&Reg8::E),
+            registers.read8(&Reg8::H), registers.read8(&Reg8::L),
+            cpu.stack_pointer, pc,
+            mmu.peek8(pc), mmu.peek8(pc.wrapping_add(1)),
+            mmu.peek8(pc.wrapping_add(2)), mmu.peek8(pc.wrapping_add(3)),
+        )
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        if let Err(err) = self.dump() {
+            eprintln!("Couldn't write trace: {}", err);
+        }
+    }
+}
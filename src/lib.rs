@@ -0,0 +1,20 @@
+//! Game Boy (DMG-01) emulator core.
+//!
+//! The [`GameBoy`] facade is the entry point for frontends, test rigs and
+//! bots: build it from ROM bytes, drive it with [`GameBoy::run_frame`] or
+//! [`GameBoy::run_cycles`], feed it [`Key`] input and read back the
+//! [`Framebuffer`] and audio samples. The individual components (`Cpu`,
+//! `Mmu`, `Ppu`, ...) stay reachable through the [`gameboy`] module.
+
+pub mod gameboy;
+pub mod rom;
+pub mod save;
+pub mod testrom;
+
+pub use gameboy::{Config, Event, GameBoy, CYCLES_PER_FRAME};
+pub use gameboy::cartridge::rtc::RtcClock;
+pub use gameboy::joypad::Key;
+pub use gameboy::model::Model;
+pub use gameboy::ppu::Renderer;
+pub use gameboy::screen::framebuffer::{Color, Framebuffer, SCREEN_H, SCREEN_W};
+pub use rom::{CartridgeHeader, RomError};
@@ -1,19 +1,60 @@
-mod rom;
-mod gameboy;
+mod frontend;
 
-use std::time::{Duration, Instant};
+use std::{path::Path, time::{Duration, Instant}};
 
-use gameboy::{screen::window::ScreenWindow, GameBoy, audio::AudioQueue};
+use frontend::{args::Args, audio::AudioQueue, window::ScreenWindow};
+use gameboy_emolator::{rom::{self, CartridgeHeader}, save::SaveFile, Config, GameBoy};
 
 
 fn main() {
-    let rom = rom::handle_rom();
+    let args = Args::parse();
+    let rom = match rom::load_rom(&args.rom_path) {
+        Ok(value) => value,
+        Err(err) => panic!("Couldn't load rom: {}", err)
+    };
+
+    if args.info {
+        match CartridgeHeader::parse(&rom) {
+            Ok(header) => println!("{}", header),
+            Err(err) => panic!("Couldn't read cartridge header: {}", err)
+        }
+        return;
+    }
 
     let frame_duration = Duration::from_micros(16742);
     let mut next_frame = Instant::now();
 
-    let mut gb = GameBoy::new(rom);
-    let mut screen = ScreenWindow::new("MoBoy - Emulator", 4);
+    let boot_rom = args.boot_rom_path.as_deref().map(|path| match rom::load_boot_rom(path) {
+        Ok(value) => value,
+        Err(err) => panic!("Couldn't load boot rom: {}", err)
+    });
+
+    let mut gb = match GameBoy::with_config(rom, Config {
+        boot_rom,
+        model: args.model,
+        renderer: args.renderer,
+        unrestricted_access: args.unrestricted_access,
+        rtc_clock: args.rtc_clock,
+    }) {
+        Ok(value) => value,
+        Err(err) => panic!("Couldn't load rom: {}", err)
+    };
+
+    let header = gb.header();
+    println!("Loaded {} ({})", header.title, header.cartridge_type_name());
+    if header.invalid_rom_size.is_some() || header.invalid_ram_size.is_some() {
+        eprintln!("Warning: unknown ROM or RAM size in the header, see --info");
+    }
+    if !header.header_checksum_valid {
+        eprintln!("Warning: header checksum mismatch, real hardware would refuse to boot this ROM");
+    }
+
+    let mut save = SaveFile::for_rom(Path::new(&args.rom_path), args.save_dir.as_deref());
+    if let Err(err) = save.load(&mut gb) {
+        panic!("Couldn't load save file {}: {}", save.path().display(), err);
+    }
+
+    let mut screen = ScreenWindow::new(&format!("MoBoy - {}", gb.header().title), 4);
     let audio = AudioQueue::new();
 
     while screen.is_open() {
@@ -27,7 +68,11 @@ fn main() {
         }
 
         if gb.step() {
-            screen.draw(&gb.ppu.framebuffer);
+            screen.draw(gb.framebuffer());
+
+            if let Err(err) = save.update(&mut gb) {
+                eprintln!("Couldn't write save file {}: {}", save.path().display(), err);
+            }
 
             next_frame += frame_duration;
             if let Some(sleep_time) = next_frame.checked_duration_since(Instant::now()) {
@@ -35,9 +80,18 @@ fn main() {
             }
         }
 
+        // There is no rumble motor to drive on a desktop and nothing plugged
+        // into the link port
+        gb.take_events();
+        gb.take_serial_output();
+
         let (left, right) = gb.get_audio_samples();
         if !left.is_empty() {
             audio.push_samples(&left, &right);
         }
     }
-}
\ No newline at end of file
+
+    if let Err(err) = save.flush(&mut gb) {
+        eprintln!("Couldn't write save file {}: {}", save.path().display(), err);
+    }
+}
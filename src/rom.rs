@@ -1,6 +1,59 @@
-use std::{env, fs::File, io::{self, Read}};
+use std::{error::Error, fmt, fs::File, io::{self, Read}};
 
-fn load_rom(path: &str) -> Result<Vec<u8>, io::Error> {
+/// Everything that can go wrong while loading a cartridge
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    /// The ROM is too small to even contain the cartridge header
+    MissingHeader(usize),
+    /// Header byte 0x0147 names a cartridge type without a mapper implementation
+    UnsupportedCartridgeType(u8),
+    /// Header byte 0x0148 is not one of the known ROM sizes
+    InvalidRomSize(u8),
+    /// Header byte 0x0149 is not one of the known RAM sizes
+    InvalidRamSize(u8),
+    /// A DMG boot ROM has to be exactly 256 bytes
+    InvalidBootRom(usize),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "{}", err),
+            RomError::MissingHeader(size) =>
+                write!(f, "ROM is too small to contain a cartridge header ({} bytes)", size),
+            RomError::UnsupportedCartridgeType(cartridge_type) =>
+                write!(f, "unsupported cartridge type 0x{:02X} ({})", cartridge_type, cartridge_type_name(*cartridge_type)),
+            RomError::InvalidRomSize(value) =>
+                write!(f, "invalid ROM size 0x{:02X} in the cartridge header", value),
+            RomError::InvalidRamSize(value) =>
+                write!(f, "invalid RAM size 0x{:02X} in the cartridge header", value),
+            RomError::InvalidBootRom(size) =>
+                write!(f, "boot ROM has {} bytes, expected {}", size, BOOT_ROM_SIZE),
+        }
+    }
+}
+
+impl Error for RomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RomError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+/// Size of the DMG boot ROM mapped over 0x0000-0x00FF
+pub const BOOT_ROM_SIZE: usize = 0x0100;
+
+/// Reads the whole ROM image at `path` into memory
+pub fn load_rom(path: &str) -> Result<Vec<u8>, RomError> {
     let mut file = File::open(path)?;
 
     let mut buffer = Vec::new();
@@ -9,28 +62,348 @@ fn load_rom(path: &str) -> Result<Vec<u8>, io::Error> {
     Ok(buffer)
 }
 
-pub fn handle_rom() -> Vec<u8> {
-    let mut rom_path: Option<String> = None;
+/// Reads a boot ROM image and checks that it has the size of the DMG boot ROM
+pub fn load_boot_rom(path: &str) -> Result<Vec<u8>, RomError> {
+    let boot_rom = load_rom(path)?;
+
+    if boot_rom.len() != BOOT_ROM_SIZE {
+        return Err(RomError::InvalidBootRom(boot_rom.len()));
+    }
+
+    Ok(boot_rom)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CgbSupport {
+    /// Plain DMG game
+    None,
+    /// Runs on DMG, uses CGB features if available
+    Enhanced,
+    /// Refuses to run on DMG
+    Only,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Licensee {
+    /// One byte code at 0x014B
+    Old(u8),
+    /// Two character code at 0x0144-0x0145, used if 0x014B is 0x33
+    New(String),
+}
+
+impl fmt::Display for Licensee {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Licensee::Old(code) => write!(f, "0x{:02X}", code),
+            Licensee::New(code) => write!(f, "\"{}\"", code),
+        }
+    }
+}
+
+/// The cartridge header at 0x0100-0x014F
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    /// Sum of the raw title bytes 0x0134-0x0143
+    pub title_checksum: u8,
+    /// Only present on later carts, which shortened the title for it
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub sgb_support: bool,
+    pub cartridge_type: u8,
+    /// From header byte 0x0148, or the file size if that byte is unknown
+    pub rom_size: usize,
+    /// From header byte 0x0149, or no RAM if that byte is unknown
+    pub ram_size: usize,
+    /// Unknown size bytes found in 0x0148 and 0x0149
+    pub invalid_rom_size: Option<u8>,
+    pub invalid_ram_size: Option<u8>,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    /// What the boot ROM computes over 0x0134-0x014C, it has to match `header_checksum`
+    pub computed_header_checksum: u8,
+    pub header_checksum_valid: bool,
+    pub global_checksum: u16,
+    pub global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, RomError> {
+        if rom.len() < 0x0150 {
+            return Err(RomError::MissingHeader(rom.len()));
+        }
+
+        let cgb_support = match rom[0x0143] {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+
+        // Carts with a manufacturer code use 0x013F-0x0142 for it, which
+        // can only be told apart by it being 4 upper case characters
+        let manufacturer = &rom[0x013F..0x0143];
+        let has_manufacturer_code = cgb_support != CgbSupport::None
+            && manufacturer.iter().all(u8::is_ascii_uppercase);
+
+        let (title, manufacturer_code) = if has_manufacturer_code {
+            (Self::text(&rom[0x0134..0x013F]), Some(Self::text(manufacturer)))
+        } else {
+            let end = if cgb_support == CgbSupport::None { 0x0144 } else { 0x0143 };
+            (Self::text(&rom[0x0134..end]), None)
+        };
+
+        // Homebrew and bad dumps sometimes have garbage in the size bytes,
+        // they still run with the size of the file and without RAM
+        let (rom_size, invalid_rom_size) = match rom[0x0148] {
+            value @ 0x00..=0x08 => ((32 * 1024) << value, None),
+            value => (rom.len(), Some(value)),
+        };
+
+        let (ram_size, invalid_ram_size) = match rom[0x0149] {
+            0x00 => (0, None),
+            0x01 => (2 * 1024, None),
+            0x02 => (8 * 1024, None),
+            0x03 => (32 * 1024, None),
+            0x04 => (128 * 1024, None),
+            0x05 => (64 * 1024, None),
+            value => (0, Some(value)),
+        };
+
+        let licensee = match rom[0x014B] {
+            0x33 => Licensee::New(Self::text(&rom[0x0144..0x0146])),
+            code => Licensee::Old(code),
+        };
+
+        let header_checksum = rom[0x014D];
+        let computed_header_checksum = rom[0x0134..=0x014C]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+
+        let global_checksum = u16::from_be_bytes([rom[0x014E], rom[0x014F]]);
+        let computed_global_checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|&(addr, _)| addr != 0x014E && addr != 0x014F)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+
+        let title_checksum = rom[0x0134..=0x0143]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+
+        Ok(Self {
+            title,
+            title_checksum,
+            manufacturer_code,
+            cgb_support,
+            sgb_support: rom[0x0146] == 0x03,
+            cartridge_type: rom[0x0147],
+            rom_size,
+            ram_size,
+            invalid_rom_size,
+            invalid_ram_size,
+            licensee,
+            version: rom[0x014C],
+            header_checksum,
+            computed_header_checksum,
+            header_checksum_valid: header_checksum == computed_header_checksum,
+            global_checksum,
+            global_checksum_valid: global_checksum == computed_global_checksum,
+        })
+    }
+
+    /// Like `parse`, but unknown size bytes are an error instead of
+    /// falling back to the file size and no RAM
+    pub fn parse_strict(rom: &[u8]) -> Result<Self, RomError> {
+        let header = Self::parse(rom)?;
+
+        if let Some(value) = header.invalid_rom_size {
+            return Err(RomError::InvalidRomSize(value));
+        }
+        if let Some(value) = header.invalid_ram_size {
+            return Err(RomError::InvalidRamSize(value));
+        }
+
+        Ok(header)
+    }
+
+    fn text(bytes: &[u8]) -> String {
+        bytes.iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    /// Whether the cartridge keeps RAM (and RTC) alive with a battery
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF)
+    }
+
+    pub fn cartridge_type_name(&self) -> &'static str {
+        cartridge_type_name(self.cartridge_type)
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let checksum_state = |valid: bool| if valid { "ok" } else { "MISMATCH" };
 
-    let mut args = env::args().skip(1);
-    while let Some(arg) = args.next() {
-        match &arg[..] {
-            "-r" | "--rom_path" => {
-                if let Some(arg_rom_path) = args.next() {
-                    rom_path = Some(arg_rom_path)
-                }
-            },
-            _ => println!("Unknown argument {}. Skipping...", arg)
+        writeln!(f, "Title:           {}", self.title)?;
+        if let Some(code) = &self.manufacturer_code {
+            writeln!(f, "Manufacturer:    {}", code)?;
+        }
+        writeln!(f, "CGB support:     {:?}", self.cgb_support)?;
+        writeln!(f, "SGB support:     {}", self.sgb_support)?;
+        writeln!(f, "Cartridge type:  0x{:02X} ({})", self.cartridge_type, self.cartridge_type_name())?;
+        write!(f, "ROM size:        {} KiB", self.rom_size / 1024)?;
+        match self.invalid_rom_size {
+            Some(value) => writeln!(f, " (invalid header value 0x{:02X}, using the file size)", value)?,
+            None => writeln!(f)?,
         }
+        write!(f, "RAM size:        {} KiB", self.ram_size / 1024)?;
+        match self.invalid_ram_size {
+            Some(value) => writeln!(f, " (invalid header value 0x{:02X}, assuming no RAM)", value)?,
+            None => writeln!(f)?,
+        }
+        writeln!(f, "Licensee:        {}", self.licensee)?;
+        writeln!(f, "Version:         {}", self.version)?;
+        writeln!(f, "Header checksum: 0x{:02X} ({})", self.header_checksum, checksum_state(self.header_checksum_valid))?;
+        write!(f, "Global checksum: 0x{:04X} ({})", self.global_checksum, checksum_state(self.global_checksum_valid))
+    }
+}
+
+pub fn cartridge_type_name(cartridge_type: u8) -> &'static str {
+    match cartridge_type {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 32 KiB ROM with `title` and the CGB flag, both checksums fixed up
+    fn rom(title: &[u8], cgb_flag: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title);
+        rom[0x0143] = cgb_flag;
+        rom[0x0147] = 0x00;
+        fix_checksums(&mut rom);
+
+        rom
     }
 
-    let rom = match rom_path {
-        Some(value) => value,
-        None => panic!("required argument '--rom_path <rom_path>' not found.")
-    };
+    fn fix_checksums(rom: &mut [u8]) {
+        rom[0x014D] = CartridgeHeader::parse(rom).unwrap().computed_header_checksum;
+
+        let sum = rom.iter().enumerate()
+            .filter(|&(addr, _)| addr != 0x014E && addr != 0x014F)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+        rom[0x014E..0x0150].copy_from_slice(&sum.to_be_bytes());
+    }
 
-    match load_rom(rom.as_str()) {
-        Ok(value) => value,
-        Err(err) => panic!("Couldn't load rom: {}", err)
+    #[test]
+    fn dmg_title_uses_all_16_bytes() {
+        let header = CartridgeHeader::parse(&rom(b"ABCDEFGHIJKLMNOP", 0x50)).unwrap();
+        assert_eq!(header.title, "ABCDEFGHIJKLMNOP");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb_support, CgbSupport::None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn cgb_flag_truncates_the_title() {
+        let header = CartridgeHeader::parse(&rom(b"POKEMON Gold", 0x80)).unwrap();
+        assert_eq!(header.title, "POKEMON Gold");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb_support, CgbSupport::Enhanced);
+
+        let header = CartridgeHeader::parse(&rom(b"ABCDEFGHIJKlmno", 0xC0)).unwrap();
+        assert_eq!(header.title, "ABCDEFGHIJKlmno");
+        assert_eq!(header.cgb_support, CgbSupport::Only);
+    }
+
+    #[test]
+    fn manufacturer_code_is_split_off() {
+        let header = CartridgeHeader::parse(&rom(b"POKEMON_SLVAAXE", 0x80)).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+
+        // Without the CGB flag these bytes are still part of the title
+        let header = CartridgeHeader::parse(&rom(b"POKEMON_SLVAAXE", 0x00)).unwrap();
+        assert_eq!(header.title, "POKEMON_SLVAAXE");
+        assert_eq!(header.manufacturer_code, None);
+    }
+
+    #[test]
+    fn checksums_are_verified() {
+        let mut rom = rom(b"TETRIS", 0x00);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.header_checksum_valid);
+        assert!(header.global_checksum_valid);
+
+        // Outside of the header only the global checksum changes
+        rom[0x0200] ^= 0xFF;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.header_checksum_valid);
+        assert!(!header.global_checksum_valid);
+
+        rom[0x014C] ^= 0x01;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.header_checksum_valid);
+    }
+
+    #[test]
+    fn invalid_size_bytes() {
+        let mut rom = rom(b"TETRIS", 0x00);
+        rom[0x0148] = 0x20;
+        assert!(matches!(CartridgeHeader::parse_strict(&rom), Err(RomError::InvalidRomSize(0x20))));
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.rom_size, 0x8000);
+        assert_eq!(header.invalid_rom_size, Some(0x20));
+
+        rom[0x0148] = 0x00;
+        rom[0x0149] = 0x06;
+        assert!(matches!(CartridgeHeader::parse_strict(&rom), Err(RomError::InvalidRamSize(0x06))));
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.ram_size, 0);
+        assert_eq!(header.invalid_ram_size, Some(0x06));
+    }
+
+    #[test]
+    fn short_rom_has_no_header() {
+        for len in [0, 0x0100, 0x014F] {
+            assert!(matches!(CartridgeHeader::parse(&vec![0; len]), Err(RomError::MissingHeader(size)) if size == len));
+            assert!(matches!(CartridgeHeader::parse_strict(&vec![0; len]), Err(RomError::MissingHeader(_))));
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::{fs, io, path::{Path, PathBuf}, time::{Duration, Instant}};
+
+use crate::GameBoy;
+
+/// Time without RAM writes after which the save file is flushed
+pub const FLUSH_DELAY: Duration = Duration::from_secs(3);
+
+/// `.sav` file backing the battery RAM of a cartridge
+pub struct SaveFile {
+    path: PathBuf,
+    last_write: Option<Instant>,
+}
+
+impl SaveFile {
+    /// Save file next to the ROM, or inside `save_dir` if given
+    pub fn for_rom(rom_path: &Path, save_dir: Option<&Path>) -> Self {
+        let path = match save_dir {
+            Some(dir) => {
+                let name = rom_path.file_stem().unwrap_or(rom_path.as_os_str());
+                // Not `with_extension`, that would cut "Game v1.1" down to "Game v1"
+                dir.join(format!("{}.sav", name.to_string_lossy()))
+            },
+            None => rom_path.with_extension("sav"),
+        };
+
+        Self::new(path)
+    }
+
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            last_write: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the save into the cartridge RAM. A missing file is not an error,
+    /// a file of the wrong size is loaded as far as possible with a warning.
+    pub fn load(&self, gb: &mut GameBoy) -> Result<(), io::Error> {
+        let Some(expected) = gb.save_data().map(|data| data.len()) else {
+            return Ok(());
+        };
+
+        let data = match fs::read(&self.path) {
+            Ok(value) => value,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        if !gb.load_save_data(&data) {
+            eprintln!(
+                "Warning: save file {} has {} bytes, expected {}",
+                self.path.display(), data.len(), expected
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Writes the cartridge RAM (and RTC) to disk
+    pub fn flush(&mut self, gb: &mut GameBoy) -> Result<(), io::Error> {
+        self.last_write = None;
+
+        let Some(data) = gb.save_data() else {
+            return Ok(());
+        };
+
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(&self.path, data)
+    }
+
+    /// Should be called regularly (e.g. once per frame). Flushes the save
+    /// once the game stopped writing to cartridge RAM for `FLUSH_DELAY`.
+    pub fn update(&mut self, gb: &mut GameBoy) -> Result<(), io::Error> {
+        if gb.take_battery_ram_dirty() {
+            self.last_write = Some(Instant::now());
+            return Ok(());
+        }
+
+        match self.last_write {
+            Some(last_write) if last_write.elapsed() >= FLUSH_DELAY => self.flush(gb),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::{env, fmt, fs, io, path::{Path, PathBuf}};
+
+use crate::{gameboy::cpu::registers::Reg8, rom::RomError, GameBoy};
+
+/// Emulated time after which a test ROM counts as hung (2 minutes)
+pub const DEFAULT_TIMEOUT_FRAMES: u64 = 60 * 120;
+
+/// Mooneye's ROMs finish within a few seconds, hanging ones are given up earlier
+pub const MOONEYE_TIMEOUT_FRAMES: u64 = 60 * 20;
+
+/// Frames to keep running after a result was printed, so the rest of the
+/// line still ends up in the output
+const TRAILING_FRAMES: u64 = 30;
+
+/// Signature at 0xA001-0xA003 that marks the result area of Blargg's ROMs
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+/// Result code at 0xA000 while the test is still running
+const BLARGG_RUNNING: u8 = 0x80;
+
+/// B, C, D, E, H and L after a passed Mooneye test
+const MOONEYE_PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+/// B, C, D, E, H and L after a failed Mooneye test
+const MOONEYE_FAILED: [u8; 6] = [0x42; 6];
+
+/// Models named in Mooneye file name suffixes. `dmgABC` and `G` (the whole
+/// DMG family) are the ones this emulator runs as.
+const MOONEYE_MODELS: [&str; 12] = ["dmgABC", "dmg0", "mgb", "sgb2", "sgb", "cgb0", "cgb", "agb", "ags", "G", "S", "C"];
+
+/// How a test ROM run ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    TimedOut,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Passed => f.pad("PASS"),
+            Outcome::Failed => f.pad("FAIL"),
+            Outcome::TimedOut => f.pad("TIMEOUT"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TestResult {
+    pub outcome: Outcome,
+    /// Whatever the ROM printed about the result
+    pub output: String,
+    pub frames: u64,
+}
+
+/// Runs one of Blargg's test ROMs until it reports a result. They print
+/// their text over the serial port, and the newer ones also keep a result
+/// code and the text in cartridge RAM at 0xA000, which is the only output
+/// of the sound tests.
+///
+/// * `result` - Fails if the ROM could not be loaded
+pub fn run_blargg(rom: Vec<u8>, timeout_frames: u64) -> Result<TestResult, RomError> {
+    let mut gb = GameBoy::new(rom)?;
+    let mut serial = String::new();
+    let mut memory_running = false;
+    let mut finished: Option<(Outcome, u64)> = None;
+
+    for frame in 1..=timeout_frames {
+        run_frame(&mut gb);
+        serial.extend(gb.take_serial_output().into_iter().map(char::from));
+
+        if let Some((outcome, at)) = finished {
+            if frame >= at + TRAILING_FRAMES {
+                return Ok(TestResult { outcome, output: serial.trim().to_string(), frames: frame });
+            }
+            continue;
+        }
+
+        let signature = [gb.peek(0xA001), gb.peek(0xA002), gb.peek(0xA003)];
+        if signature == BLARGG_SIGNATURE {
+            let status = gb.peek(0xA000);
+
+            if status == BLARGG_RUNNING {
+                memory_running = true;
+            } else if memory_running {
+                let outcome = if status == 0 { Outcome::Passed } else { Outcome::Failed };
+                return Ok(TestResult { outcome, output: blargg_memory_text(&gb), frames: frame });
+            }
+        }
+
+        if let Some(outcome) = blargg_serial_outcome(&serial) {
+            finished = Some((outcome, frame));
+        }
+    }
+
+    let output = if memory_running { blargg_memory_text(&gb) } else { serial.trim().to_string() };
+    Ok(TestResult { outcome: finished.map_or(Outcome::TimedOut, |(outcome, _)| outcome), output, frames: timeout_frames })
+}
+
+/// Runs one frame and drops the audio and events nobody listens to, so
+/// the buffers don't grow over a run of several minutes of emulated time
+fn run_frame(gb: &mut GameBoy) {
+    gb.run_frame();
+    gb.get_audio_samples();
+    gb.take_events();
+}
+
+/// Runs one of the Mooneye test ROMs until it hits the `LD B, B` breakpoint
+/// they end with. A passed test leaves the Fibonacci numbers 3, 5, 8, 13,
+/// 21 and 34 in B to L.
+///
+/// * `result` - Fails if the ROM could not be loaded
+pub fn run_mooneye(rom: Vec<u8>, timeout_frames: u64) -> Result<TestResult, RomError> {
+    let mut gb = GameBoy::new(rom)?;
+
+    for frame in 1..=timeout_frames {
+        run_frame(&mut gb);
+        gb.take_serial_output();
+
+        if gb.take_breakpoint() {
+            let registers = &gb.cpu().registers;
+            let values = [Reg8::B, Reg8::C, Reg8::D, Reg8::E, Reg8::H, Reg8::L].map(|reg| registers.read8(&reg));
+
+            let outcome = if values == MOONEYE_PASSED { Outcome::Passed } else { Outcome::Failed };
+            let output = match values {
+                MOONEYE_PASSED => "Passed".to_string(),
+                MOONEYE_FAILED => "Failed".to_string(),
+                _ => format!(
+                    "Failed: B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X}",
+                    values[0], values[1], values[2], values[3], values[4], values[5]
+                ),
+            };
+
+            return Ok(TestResult { outcome, output, frames: frame });
+        }
+    }
+
+    Ok(TestResult { outcome: Outcome::TimedOut, output: String::new(), frames: timeout_frames })
+}
+
+/// Mooneye ROMs meant for specific models carry them after the last dash of
+/// the file name, e.g. `boot_regs-dmg0.gb` or `boot_div-S.gb`. ROMs without
+/// such a suffix run on every model.
+pub fn mooneye_runs_on_dmg(path: &Path) -> bool {
+    let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+        return true;
+    };
+    let Some((_, mut suffix)) = stem.rsplit_once('-') else {
+        return true;
+    };
+
+    let mut models = Vec::new();
+    while !suffix.is_empty() {
+        match MOONEYE_MODELS.iter().find(|model| suffix.starts_with(*model)) {
+            Some(model) => {
+                models.push(*model);
+                suffix = &suffix[model.len()..];
+            },
+            // Not a list of models, just part of the test name
+            None => return true,
+        }
+    }
+
+    models.iter().any(|&model| model == "dmgABC" || model == "G")
+}
+
+/// Looks for the final "Passed"/"Failed" line in the serial output
+fn blargg_serial_outcome(serial: &str) -> Option<Outcome> {
+    let passed = serial.rfind("Passed");
+    let failed = serial.rfind("Failed");
+
+    match (passed, failed) {
+        (Some(passed), Some(failed)) if failed > passed => Some(Outcome::Failed),
+        (Some(_), _) => Some(Outcome::Passed),
+        (None, Some(_)) => Some(Outcome::Failed),
+        (None, None) => None,
+    }
+}
+
+/// Zero terminated text starting at 0xA004
+fn blargg_memory_text(gb: &GameBoy) -> String {
+    (0xA004..=0xBFFF)
+        .map(|addr| gb.peek(addr))
+        .take_while(|&byte| byte != 0)
+        .map(char::from)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Directory with test data that is not part of the repository: `$var`,
+/// or `default` relative to the crate root
+///
+/// * `result` - Fails if the directory does not exist
+pub fn data_dir(var: &str, default: &str) -> io::Result<PathBuf> {
+    let dir = PathBuf::from(env::var(var).unwrap_or_else(|_| default.to_string()));
+
+    if !dir.is_dir() {
+        let message = format!("{} does not exist, point ${} to the test data", dir.display(), var);
+        return Err(io::Error::new(io::ErrorKind::NotFound, message));
+    }
+
+    Ok(dir)
+}
+
+/// `path` itself if it is a file, otherwise every `.gb` file below it in
+/// sorted order
+pub fn find_roms(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut roms = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            roms.extend(find_roms(&path)?);
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gb")) {
+            roms.push(path);
+        }
+    }
+
+    roms.sort();
+    Ok(roms)
+}
+
+/// One line per ROM with its outcome and the last line of its output,
+/// followed by the number of passed tests
+pub fn results_table(results: &[(String, TestResult)]) -> String {
+    let width = results.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let passed = results.iter().filter(|(_, result)| result.outcome == Outcome::Passed).count();
+
+    let mut table = String::new();
+    for (name, result) in results {
+        table += &format!("{:<width$}  {:<7}  {}\n", name, result.outcome, last_line(&result.output), width = width);
+    }
+    table += &format!("{}/{} passed\n", passed, results.len());
+
+    table
+}
+
+/// The last line of the output usually sums the result up
+fn last_line(text: &str) -> &str {
+    text.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("")
+}
@@ -0,0 +1,64 @@
+//! Blargg's test ROMs, which are not part of the repository. They are
+//! looked up in `$BLARGG_ROMS` (default `test-roms/blargg`) in the layout of
+//! the original archive, e.g. `cpu_instrs/individual/01-special.gb` or
+//! `halt_bug.gb`. The tests are ignored by default, run them with
+//! `cargo test -- --ignored`.
+
+use gameboy_emolator::{rom, testrom::{self, Outcome, DEFAULT_TIMEOUT_FRAMES}};
+
+fn run_suite(name: &str) {
+    let dir = testrom::data_dir("BLARGG_ROMS", "test-roms/blargg").unwrap();
+    let path = [dir.join(name), dir.join(format!("{}.gb", name))]
+        .into_iter()
+        .find(|path| path.exists())
+        .unwrap_or_else(|| panic!("no {} ROMs in {}", name, dir.display()));
+
+    let mut results = Vec::new();
+    for rom_path in testrom::find_roms(&path).unwrap() {
+        let rom = rom::load_rom(&rom_path.to_string_lossy()).unwrap();
+        let result = testrom::run_blargg(rom, DEFAULT_TIMEOUT_FRAMES).unwrap();
+
+        results.push((rom_path.strip_prefix(&dir).unwrap_or(&rom_path).display().to_string(), result));
+    }
+
+    let table = testrom::results_table(&results);
+    println!("{}", table);
+
+    assert!(results.iter().all(|(_, result)| result.outcome == Outcome::Passed), "{}", table);
+}
+
+#[test]
+#[ignore = "needs Blargg's test ROMs in $BLARGG_ROMS"]
+fn cpu_instrs() {
+    run_suite("cpu_instrs");
+}
+
+#[test]
+#[ignore = "needs Blargg's test ROMs in $BLARGG_ROMS"]
+fn instr_timing() {
+    run_suite("instr_timing");
+}
+
+#[test]
+#[ignore = "needs Blargg's test ROMs in $BLARGG_ROMS"]
+fn mem_timing() {
+    run_suite("mem_timing");
+}
+
+#[test]
+#[ignore = "needs Blargg's test ROMs in $BLARGG_ROMS"]
+fn halt_bug() {
+    run_suite("halt_bug");
+}
+
+#[test]
+#[ignore = "needs Blargg's test ROMs in $BLARGG_ROMS"]
+fn dmg_sound() {
+    run_suite("dmg_sound");
+}
+
+#[test]
+#[ignore = "needs Blargg's test ROMs in $BLARGG_ROMS"]
+fn oam_bug() {
+    run_suite("oam_bug");
+}
@@ -0,0 +1,80 @@
+//! Screenshot tests: a ROM runs for a fixed number of frames and the last
+//! frame has to match a reference PNG shade by shade. On a mismatch the
+//! screenshot and a diff image with the differing pixels in red are written
+//! to the target directory. ROMs and references are not part of the
+//! repository.
+//!
+//! dmg-acid2 is looked up in `$DMG_ACID2` (default `test-roms/dmg-acid2`) as
+//! `dmg-acid2.gb` and `reference-dmg.png`. Further screenshots are listed in
+//! `$GOLDEN_ROMS/golden.txt` (default `test-roms/golden`), one
+//! `<rom> <frames> <reference png>` per line, paths relative to that file.
+//! The tests are ignored by default, run them with `cargo test -- --ignored`.
+
+use std::{fs, path::Path};
+
+use gameboy_emolator::{gameboy::screen::image, rom, testrom, GameBoy};
+
+/// dmg-acid2 draws its picture once and then waits
+const DMG_ACID2_FRAMES: u64 = 60;
+
+/// Runs `rom_path` for `frames` frames and compares the last one
+///
+/// * `result` - Returns a description of the mismatch, if any
+fn check_screenshot(name: &str, rom_path: &Path, frames: u64, reference_path: &Path) -> Option<String> {
+    let rom = rom::load_rom(&rom_path.to_string_lossy()).unwrap();
+    let reference = image::load_png(reference_path).unwrap();
+
+    let mut gb = GameBoy::new(rom).unwrap();
+    for _ in 0..frames {
+        gb.run_frame();
+        gb.get_audio_samples();
+    }
+
+    let diff = image::compare(gb.framebuffer(), &reference)?;
+
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    fs::create_dir_all(&out_dir).unwrap();
+
+    let actual_path = out_dir.join(format!("{}.png", name));
+    let diff_path = out_dir.join(format!("{}-diff.png", name));
+    image::save_png(&image::to_greyscale(gb.framebuffer()), &actual_path).unwrap();
+    image::save_png(&diff.image, &diff_path).unwrap();
+
+    Some(format!(
+        "{}: {} pixels differ from {}, got {}, diff {}",
+        name, diff.pixels, reference_path.display(), actual_path.display(), diff_path.display()
+    ))
+}
+
+#[test]
+#[ignore = "needs dmg-acid2 in $DMG_ACID2"]
+fn dmg_acid2() {
+    let dir = testrom::data_dir("DMG_ACID2", "test-roms/dmg-acid2").unwrap();
+    let rom_path = dir.join("dmg-acid2.gb");
+
+    if let Some(mismatch) = check_screenshot("dmg-acid2", &rom_path, DMG_ACID2_FRAMES, &dir.join("reference-dmg.png")) {
+        panic!("{}", mismatch);
+    }
+}
+
+#[test]
+#[ignore = "needs golden.txt and its ROMs in $GOLDEN_ROMS"]
+fn screenshots() {
+    let dir = testrom::data_dir("GOLDEN_ROMS", "test-roms/golden").unwrap();
+    let manifest = fs::read_to_string(dir.join("golden.txt")).unwrap();
+
+    let mut mismatches = Vec::new();
+    for line in manifest.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let [rom, frames, reference] = parts[..] else {
+            panic!("expected '<rom> <frames> <reference png>', got '{}'", line);
+        };
+
+        let name = format!("{}-{}", Path::new(rom).file_stem().unwrap().to_string_lossy(), frames);
+        if let Some(mismatch) = check_screenshot(&name, &dir.join(rom), frames.parse().unwrap(), &dir.join(reference)) {
+            mismatches.push(mismatch);
+        }
+    }
+
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
@@ -0,0 +1,119 @@
+//! Mooneye's test ROMs, which are not part of the repository. They are
+//! looked up in `$MOONEYE_ROMS` (default `test-roms/mooneye`) in the layout
+//! of the mooneye-test-suite build, e.g. `acceptance/timer/div_write.gb` or
+//! `emulator-only/mbc1/bits_bank1.gb`. The tests are ignored by default,
+//! run them with `cargo test -- --ignored`.
+
+use std::path::{Path, PathBuf};
+
+use gameboy_emolator::{rom, testrom::{self, Outcome, MOONEYE_TIMEOUT_FRAMES}};
+
+fn rom_dir() -> PathBuf {
+    testrom::data_dir("MOONEYE_ROMS", "test-roms/mooneye").unwrap()
+}
+
+fn run_roms(name: &str, roms: Vec<PathBuf>, dir: &Path) {
+    let mut results = Vec::new();
+    for rom_path in roms {
+        if !testrom::mooneye_runs_on_dmg(&rom_path) {
+            continue;
+        }
+
+        let rom = rom::load_rom(&rom_path.to_string_lossy()).unwrap();
+        let result = testrom::run_mooneye(rom, MOONEYE_TIMEOUT_FRAMES).unwrap();
+
+        results.push((rom_path.strip_prefix(dir).unwrap_or(&rom_path).display().to_string(), result));
+    }
+
+    let table = testrom::results_table(&results);
+    println!("{}", name);
+    println!("{}", table);
+
+    assert!(results.iter().all(|(_, result)| result.outcome == Outcome::Passed), "{}", table);
+}
+
+fn run_suite(name: &str) {
+    let dir = rom_dir();
+    let path = dir.join(name);
+
+    run_roms(name, testrom::find_roms(&path).unwrap(), &dir);
+}
+
+#[test]
+#[ignore = "needs the Mooneye test ROMs in $MOONEYE_ROMS"]
+fn acceptance_general() {
+    let dir = rom_dir();
+    let path = dir.join("acceptance");
+
+    // Only the ROMs directly inside acceptance/, the subdirectories have their own tests
+    let mut roms = Vec::new();
+    for entry in std::fs::read_dir(&path).unwrap() {
+        let rom_path = entry.unwrap().path();
+        if rom_path.extension().is_some_and(|ext| ext == "gb") {
+            roms.push(rom_path);
+        }
+    }
+    roms.sort();
+
+    run_roms("acceptance", roms, &dir);
+}
+
+#[test]
+#[ignore = "needs the Mooneye test ROMs in $MOONEYE_ROMS"]
+fn acceptance_bits() {
+    run_suite("acceptance/bits");
+}
+
+#[test]
+#[ignore = "needs the Mooneye test ROMs in $MOONEYE_ROMS"]
+fn acceptance_instr() {
+    run_suite("acceptance/instr");
+}
+
+#[test]
+#[ignore = "needs the Mooneye test ROMs in $MOONEYE_ROMS"]
+fn acceptance_interrupts() {
+    run_suite("acceptance/interrupts");
+}
+
+#[test]
+#[ignore = "needs the Mooneye test ROMs in $MOONEYE_ROMS"]
+fn acceptance_oam_dma() {
+    run_suite("acceptance/oam_dma");
+}
+
+#[test]
+#[ignore = "needs the Mooneye test ROMs in $MOONEYE_ROMS"]
+fn acceptance_ppu() {
+    run_suite("acceptance/ppu");
+}
+
+#[test]
+#[ignore = "needs the Mooneye test ROMs in $MOONEYE_ROMS"]
+fn acceptance_serial() {
+    run_suite("acceptance/serial");
+}
+
+#[test]
+#[ignore = "needs the Mooneye test ROMs in $MOONEYE_ROMS"]
+fn acceptance_timer() {
+    run_suite("acceptance/timer");
+}
+
+#[test]
+#[ignore = "needs the Mooneye test ROMs in $MOONEYE_ROMS"]
+fn mbc1() {
+    run_suite("emulator-only/mbc1");
+}
+
+#[test]
+#[ignore = "needs the Mooneye test ROMs in $MOONEYE_ROMS"]
+fn mbc2() {
+    run_suite("emulator-only/mbc2");
+}
+
+#[test]
+#[ignore = "needs the Mooneye test ROMs in $MOONEYE_ROMS"]
+fn mbc5() {
+    run_suite("emulator-only/mbc5");
+}
@@ -0,0 +1,269 @@
+//! Per-opcode CPU tests from SingleStepTests/sm83, which are not part of the
+//! repository. The JSON files (`00.json` to `ff.json` and `cb 00.json` to
+//! `cb ff.json`) are looked up in `$SM83_TESTS` (default `test-roms/sm83`).
+//! Every test runs a single instruction on a flat 64 KiB bus and compares
+//! registers, memory and the bus access of every M-cycle. The vectors are
+//! ignored by default, run them with `cargo test -- --ignored`. A few cases
+//! in the same format are kept inline, so the bus recording is always checked.
+
+use std::{fs, path::PathBuf};
+
+use gameboy_emolator::{gameboy::{bus::{Access, Bus, FlatBus}, cpu::{registers::{Reg16, Reg8}, Cpu}}, testrom};
+use serde_json::Value;
+
+/// Failures printed in full, the rest is only counted
+const REPORTED_FAILURES: usize = 20;
+
+/// `LD [HL], A`, `PUSH BC`, `CALL $2000`, `RET NZ` taken and not taken and
+/// two interrupt dispatches in the SingleStepTests format. In the second one
+/// the push of PC overwrites IE, which cancels the dispatch.
+const INLINE_TESTS: &str = r#"[
+    {
+        "name": "77 inline",
+        "initial": {
+            "a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 192, "l": 0,
+            "pc": 256, "sp": 65534, "ime": 0,
+            "ram": [[256, 119], [49152, 0]]
+        },
+        "final": {
+            "a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 192, "l": 0,
+            "pc": 257, "sp": 65534, "ime": 0,
+            "ram": [[256, 119], [49152, 66]]
+        },
+        "cycles": [[256, 119, "r-m"], [49152, 66, "-wm"]]
+    },
+    {
+        "name": "c5 inline",
+        "initial": {
+            "a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 176, "h": 0, "l": 0,
+            "pc": 256, "sp": 53248, "ime": 0,
+            "ram": [[256, 197]]
+        },
+        "final": {
+            "a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 176, "h": 0, "l": 0,
+            "pc": 257, "sp": 53246, "ime": 0,
+            "ram": [[256, 197], [53247, 18], [53246, 52]]
+        },
+        "cycles": [[256, 197, "r-m"], [null, null, "---"], [53247, 18, "-wm"], [53246, 52, "-wm"]]
+    },
+    {
+        "name": "cd inline",
+        "initial": {
+            "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+            "pc": 256, "sp": 53248, "ime": 0,
+            "ram": [[256, 205], [257, 0], [258, 32]]
+        },
+        "final": {
+            "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+            "pc": 8192, "sp": 53246, "ime": 0,
+            "ram": [[53247, 1], [53246, 3]]
+        },
+        "cycles": [
+            [256, 205, "r-m"], [257, 0, "r-m"], [258, 32, "r-m"],
+            [null, null, "---"], [53247, 1, "-wm"], [53246, 3, "-wm"]
+        ]
+    },
+    {
+        "name": "c0 inline taken",
+        "initial": {
+            "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+            "pc": 256, "sp": 53246, "ime": 0,
+            "ram": [[256, 192], [53246, 0], [53247, 32]]
+        },
+        "final": {
+            "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+            "pc": 8192, "sp": 53248, "ime": 0,
+            "ram": [[256, 192], [53246, 0], [53247, 32]]
+        },
+        "cycles": [
+            [256, 192, "r-m"], [null, null, "---"], [53246, 0, "r-m"], [53247, 32, "r-m"],
+            [null, null, "---"]
+        ]
+    },
+    {
+        "name": "c0 inline not taken",
+        "initial": {
+            "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 128, "h": 0, "l": 0,
+            "pc": 256, "sp": 53246, "ime": 0,
+            "ram": [[256, 192], [53246, 0], [53247, 32]]
+        },
+        "final": {
+            "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 128, "h": 0, "l": 0,
+            "pc": 257, "sp": 53246, "ime": 0,
+            "ram": [[256, 192], [53246, 0], [53247, 32]]
+        },
+        "cycles": [[256, 192, "r-m"], [null, null, "---"]]
+    },
+    {
+        "name": "vblank dispatch inline",
+        "initial": {
+            "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+            "pc": 256, "sp": 53248, "ime": 1, "ie": 1,
+            "ram": [[65295, 1]]
+        },
+        "final": {
+            "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+            "pc": 64, "sp": 53246, "ime": 0,
+            "ram": [[53247, 1], [53246, 0], [65295, 0]]
+        },
+        "cycles": [
+            [null, null, "---"], [null, null, "---"], [53247, 1, "-wm"], [53246, 0, "-wm"],
+            [null, null, "---"]
+        ]
+    },
+    {
+        "name": "timer dispatch cancelled inline",
+        "initial": {
+            "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+            "pc": 512, "sp": 0, "ime": 1, "ie": 4,
+            "ram": [[65295, 4]]
+        },
+        "final": {
+            "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+            "pc": 0, "sp": 65534, "ime": 0,
+            "ram": [[65535, 2], [65534, 0], [65295, 4]]
+        },
+        "cycles": [
+            [null, null, "---"], [null, null, "---"], [65535, 2, "-wm"], [65534, 0, "-wm"],
+            [null, null, "---"]
+        ]
+    }
+]"#;
+
+const REGISTERS: [(&str, Reg8); 7] = [
+    ("a", Reg8::A), ("b", Reg8::B), ("c", Reg8::C), ("d", Reg8::D),
+    ("e", Reg8::E), ("h", Reg8::H), ("l", Reg8::L),
+];
+
+fn number(state: &Value, key: &str) -> u16 {
+    state[key].as_u64().unwrap_or_else(|| panic!("missing field {}", key)) as u16
+}
+
+fn setup(state: &Value) -> (Cpu, FlatBus) {
+    let mut cpu = Cpu::new();
+    let mut bus = FlatBus::new();
+
+    for (key, reg) in &REGISTERS {
+        cpu.registers.write8(reg, number(state, key) as u8);
+    }
+    let a = cpu.registers.read8(&Reg8::A) as u16;
+    cpu.registers.write16(&Reg16::AF, (a << 8) | number(state, "f"));
+
+    cpu.program_counter = number(state, "pc");
+    cpu.stack_pointer = number(state, "sp");
+    cpu.ime = state["ime"].as_u64() == Some(1);
+
+    for entry in state["ram"].as_array().unwrap() {
+        bus.memory[entry[0].as_u64().unwrap() as usize] = entry[1].as_u64().unwrap() as u8;
+    }
+    if let Some(ie) = state["ie"].as_u64() {
+        bus.memory[0xFFFF] = ie as u8;
+    }
+
+    (cpu, bus)
+}
+
+fn expected_accesses(cycles: &Value) -> Vec<Access> {
+    cycles.as_array().unwrap().iter().map(|cycle| {
+        let addr = cycle[0].as_u64().map(|addr| addr as u16);
+        let value = cycle[1].as_u64().map(|value| value as u8);
+
+        match (addr, value, cycle[2].as_str()) {
+            (Some(addr), Some(value), Some(kind)) if kind.starts_with('r') => Access::Read(addr, value),
+            (Some(addr), Some(value), Some(kind)) if kind.contains('w') => Access::Write(addr, value),
+            _ => Access::Idle,
+        }
+    }).collect()
+}
+
+/// Runs one test and describes every difference to the final state
+fn run_test(test: &Value) -> Vec<String> {
+    let (mut cpu, mut bus) = setup(&test["initial"]);
+    cpu.step(&mut bus);
+
+    let expected = &test["final"];
+    let mut errors = Vec::new();
+
+    let mut check = |name: &str, actual: u16, wanted: u16| {
+        if actual != wanted {
+            errors.push(format!("{}: {:04X}, expected {:04X}", name, actual, wanted));
+        }
+    };
+
+    for (key, reg) in &REGISTERS {
+        check(key, cpu.registers.read8(reg) as u16, number(expected, key));
+    }
+    check("f", cpu.registers.flag_register.flags as u16, number(expected, "f"));
+    check("pc", cpu.program_counter, number(expected, "pc"));
+    check("sp", cpu.stack_pointer, number(expected, "sp"));
+    if let Some(ime) = expected["ime"].as_u64() {
+        // EI only takes effect after the next instruction
+        check("ime", (cpu.ime || cpu.pending_ime) as u16, ime as u16);
+    }
+
+    for entry in expected["ram"].as_array().unwrap() {
+        let addr = entry[0].as_u64().unwrap() as u16;
+        check(&format!("[{:04X}]", addr), bus.read8(addr) as u16, entry[1].as_u64().unwrap() as u16);
+    }
+
+    let accesses = expected_accesses(&test["cycles"]);
+    if bus.accesses != accesses {
+        errors.push(format!("bus: {:?}, expected {:?}", bus.accesses, accesses));
+    }
+
+    errors
+}
+
+#[test]
+fn sm83_inline() {
+    let tests: Value = serde_json::from_str(INLINE_TESTS).unwrap();
+
+    for test in tests.as_array().unwrap() {
+        let errors = run_test(test);
+        assert!(errors.is_empty(), "{}: {}", test["name"].as_str().unwrap(), errors.join(", "));
+    }
+}
+
+#[test]
+#[ignore = "needs the SingleStepTests sm83 vectors in $SM83_TESTS"]
+fn sm83_single_step() {
+    let dir = testrom::data_dir("SM83_TESTS", "test-roms/sm83").unwrap();
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "no JSON files in {}", dir.display());
+
+    let mut total = 0;
+    let mut failures = Vec::new();
+    let mut failed_files = Vec::new();
+
+    for path in &files {
+        let tests: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        let mut failed = 0;
+
+        for test in tests.as_array().unwrap() {
+            total += 1;
+
+            let errors = run_test(test);
+            if !errors.is_empty() {
+                failed += 1;
+                failures.push(format!("{}: {}", test["name"].as_str().unwrap_or("?"), errors.join(", ")));
+            }
+        }
+
+        if failed > 0 {
+            failed_files.push(format!("{} ({} failed)", path.file_name().unwrap().to_string_lossy(), failed));
+        }
+    }
+
+    for failure in failures.iter().take(REPORTED_FAILURES) {
+        println!("{}", failure);
+    }
+    println!("{}/{} passed, {} files with failures: {}", total - failures.len(), total, failed_files.len(), failed_files.join(", "));
+
+    assert!(failures.is_empty());
+}